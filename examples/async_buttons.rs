@@ -0,0 +1,60 @@
+//! Toggle the LEDs on button presses, using the asynchronous API and sleeping in between.
+//!
+//! Each press of a button toggles its LED (PB0 the red, PB1 the green one), and then the buttons
+//! are ignored for a bit to debounce them. While waiting, the chip sleeps in EM2 instead of
+//! polling the buttons; both the GPIO lines and the RTCC can wake it from there.
+
+#![no_main]
+#![no_std]
+
+extern crate panic_semihosting;
+
+use efr32xg1::interrupt;
+
+use cortex_m_rt::entry;
+
+use thunderboard_sltb001a::{button::Button, executor::block_on_sleeping, exti, sleep, time_driver};
+
+#[entry]
+fn main() -> ! {
+    let board = thunderboard_sltb001a::Board::new();
+    let mut leds = board.leds;
+    let mut buttons = board.buttons;
+    let mut nvic = board.nvic;
+
+    time_driver::init(board.rtc);
+    let mut delay = time_driver::AsyncDelay::new();
+
+    nvic.enable(efr32xg1::Interrupt::GPIO_EVEN);
+    nvic.enable(efr32xg1::Interrupt::GPIO_ODD);
+    nvic.enable(efr32xg1::Interrupt::RTCC);
+
+    block_on_sleeping(sleep::Mode::Em2, async {
+        let (mut led0, mut led1) = (false, false);
+        loop {
+            match buttons.wait_for_press().await {
+                Button::Button0 => led0 = !led0,
+                Button::Button1 => led1 = !led1,
+            }
+            if led0 { leds.led0_on() } else { leds.led0_off() }
+            if led1 { leds.led1_on() } else { leds.led1_off() }
+
+            delay.delay_ms(50).await;
+        }
+    })
+}
+
+#[interrupt]
+fn GPIO_EVEN() {
+    exti::on_interrupt();
+}
+
+#[interrupt]
+fn GPIO_ODD() {
+    exti::on_interrupt();
+}
+
+#[interrupt]
+fn RTCC() {
+    time_driver::on_interrupt();
+}
@@ -38,6 +38,6 @@ fn main() -> ! {
         leds.led1_off();
 
         count = (count + 1) % 4;
-        pic.set_leds(count == 0, count == 1, count == 2, count == 3);
+        pic.set_leds(count == 0, count == 1, count == 2, count == 3).unwrap();
     }
 }
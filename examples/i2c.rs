@@ -1,7 +1,7 @@
 //! Do a bus scan of the I2C bus, and read some values.
 //!
-//! How the I2C bus is taken is a bit odd and symptomatic of the issue of sharing a peripheral
-//! between multiple users: It is taken from the PIC abstraction's cold dead hands.
+//! The scan powers the sensor domains through the PIC and identifies the devices it finds; the
+//! Si1133 is then set up and measured through a proxy to the board's shared I2C bus.
 //!
 //! The example prints to semihosted stdout (watch your OpenOCD console), and then ends in a loop.
 
@@ -12,45 +12,33 @@ extern crate panic_semihosting;
 
 use cortex_m_rt::entry;
 
-use efm32gg_hal::i2c::{ConfiguredI2C0, Error::AddressNack};
+use thunderboard_sltb001a::probe::{Device, Domains};
+use thunderboard_sltb001a::si1133::{self, Si1133};
 use cortex_m_semihosting::hio;
 use core::fmt::Write;
 
 #[entry]
 fn main() -> ! {
-    let board = thunderboard_sltb001a::Board::new();
-    let mut pic = board.pic;
-
-    pic.set_env_sensor(true);
-    pic.set_ccs(true, true);
-    writeln!(hio::hstdout().unwrap(), "Firmware version: {:?}", pic.read_firmware_version()).unwrap();
-    writeln!(hio::hstdout().unwrap(), "Interrupts set: {:?}", pic.pending_int()).unwrap();
-
-    let (mut i2c, _) = pic.destroy();
-
-    /// Scan a bus address, report if an Ack came back.
-    fn scan(i2c: &mut ConfiguredI2C0, addr: u8) {
-        let mut buf = [0u8; 1];
-        let result = i2c.read(addr, &mut buf);
-        match result {
-            Err(AddressNack) => (),
-            result => writeln!(hio::hstdout().unwrap(), "From {:#x}: {:?} ({:x?})", addr, result, buf).unwrap()
-        }
-    }
-    for addr in 0..128u8
-    {
-        scan(&mut i2c, addr);
-    }
+    let mut board = thunderboard_sltb001a::Board::new();
 
-    // Play with the SL1133. Getting it to do more would involve the choice of what exactly to read
-    // from it, and how often, and when to fetch the data. (A one-shot read-everything would be
-    // nice, would probably mean forced mode.)
-    use embedded_hal::blocking::i2c::{Write, Read};
-    i2c.write(0x55, &[0]).unwrap();
-    let mut devicedata = [0u8; 3];
-    i2c.read(0x55, &mut devicedata).unwrap();
-    assert!(devicedata == [0x33, 0x03, 0x10]);
+    let inventory = board.probe_i2c(Domains { env_sensor: true, ccs: true });
+    for found in inventory.iter() {
+        writeln!(hio::hstdout().unwrap(), "At {:#x}: {:?}", found.address, found.device).unwrap();
+    }
 
+    writeln!(hio::hstdout().unwrap(), "Firmware version: {:?}", board.pic.read_firmware_version().unwrap()).unwrap();
+    writeln!(hio::hstdout().unwrap(), "Interrupts set: {:?}", board.pic.pending_int().unwrap()).unwrap();
+
+    // One-shot measurement of all of the Si1133's channels in forced mode
+    let si1133 = inventory.address_of(Device::Si1133).expect("No Si1133 found");
+    assert_eq!(si1133, thunderboard_sltb001a::si1133::ADDR);
+    let mut light = Si1133::new(board.i2c_bus.acquire());
+    writeln!(hio::hstdout().unwrap(), "Si1133 part ID: {:#x}", light.part_id().unwrap()).unwrap();
+    light.init(&mut board.delay).unwrap();
+    let samples = light.measure_all(&mut board.delay).unwrap();
+    writeln!(hio::hstdout().unwrap(), "Si1133 samples: UV {:?}, visible {:?} / {:?}, IR {:?}",
+             samples.get(si1133::UV), samples.get(si1133::VISIBLE_HIGH),
+             samples.get(si1133::VISIBLE_LOW), samples.get(si1133::IR)).unwrap();
 
     loop { }
 }
@@ -1,28 +1,26 @@
-//! The same behavior as blink, but implemented using interrupts. Don't worry that it looks quite a
-//! bit different -- try shaking the device to see that it does blink. Having a lower blink rate
-//! would require using a timer that's currently not implemented in the HAL crate.
+//! The same behavior as blink, but implemented using interrupts: TIMER1 is set up to fire every
+//! 500ms, and the interrupt handler does one half-step of the blink loop each time.
 //!
-//! This requires nightly as it uses a statically initialized queue (would be needlessly
-//! verbose without const-fn).
+//! The board parts the handler needs are handed over through an InterruptShared slot, from where
+//! the handler moves them into its own static on its first invocation.
 
 #![no_main]
 #![no_std]
-#![feature(const_fn)]
 
 extern crate panic_semihosting;
 
 use efr32xg1::interrupt;
 
 use cortex_m_rt::entry;
-use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::timer::CountDown;
+use thunderboard_sltb001a::time::Milliseconds;
 
 #[entry]
 fn main() -> ! {
     let board = thunderboard_sltb001a::Board::new();
-    let mut leds = board.leds;
+    let leds = board.leds;
     let buttons = board.buttons;
-    let mut delay = board.delay;
-    let mut pic = board.pic;
+    let pic = board.pic;
     let mut nvic = board.nvic;
 
     nvic.enable(efr32xg1::Interrupt::TIMER1);
@@ -30,9 +28,7 @@ fn main() -> ! {
     // Show that nothing bad happens even if we call this too early
     cortex_m::peripheral::NVIC::pend(efr32xg1::Interrupt::TIMER1);
 
-    // unsafe: The SPSC documentation does it too.
-    let mut for_timer1 = unsafe { FOR_TIMER1.split().0 };
-    for_timer1.enqueue((buttons, leds, pic)).ok().unwrap();
+    FOR_TIMER1.put((buttons, leds, pic)).ok().unwrap();
 
     // Matter of taste: I rather make sure all the initialization work is done before we really
     // start spinning
@@ -40,34 +36,31 @@ fn main() -> ! {
 
     let mut timer1 = board.timer1;
 
-    timer1.enable_outputcompare(0);
-    timer1.interrupt_enable(efm32gg_hal::timer::InterruptFlag::CC0);
-    timer1.start();
+    timer1.listen();
+    timer1.start(Milliseconds(500));
 
     loop {
     }
 }
 
-use heapless::spsc::Queue;
-use heapless::consts::U1;
-use thunderboard_sltb001a::{button::Buttons, pic::PIC, RefCellDelay};
+use thunderboard_sltb001a::{button::Buttons, pic::PIC, delay::SharedDelay, timer::Timer1};
+use thunderboard_sltb001a::shared::InterruptShared;
+use thunderboard_sltb001a::i2c_bus::I2cProxy;
 #[cfg(feature = "led-pwm")]
 use thunderboard_sltb001a::led_pwm::LEDs;
 #[cfg(not(feature = "led-pwm"))]
 use thunderboard_sltb001a::led::LEDs;
 
 
-// Queue along which peripherals are moved into the timer.
-// See https://github.com/rust-embedded/wg/issues/294 for future safe directions.
-// It would feel a tad more safe to .split() this right away, but the signature 'd get ugly.
-static mut FOR_TIMER1: Queue<(Buttons, LEDs, PIC<RefCellDelay>), U1> = Queue::new();
+// Slot along which peripherals are moved into the timer.
+static FOR_TIMER1: InterruptShared<(Buttons, LEDs, PIC<SharedDelay, I2cProxy<'static>>)> = InterruptShared::new();
 
 #[interrupt]
 fn TIMER1() {
-    static mut stuff: Option<(Buttons, LEDs, PIC<RefCellDelay>)> = None;
+    static mut stuff: Option<(Buttons, LEDs, PIC<SharedDelay, I2cProxy<'static>>)> = None;
     static mut halfcount: i32 = 0;
 
-    efm32gg_hal::timer::Timer0::interrupt_unpend(efm32gg_hal::timer::InterruptFlag::CC0);
+    Timer1::interrupt_unpend();
 
     if let Some((buttons, leds, pic)) = stuff {
 
@@ -75,7 +68,9 @@ fn TIMER1() {
         let phase = *halfcount % 2;
         match phase {
             0 => {
-                pic.set_leds(count == 0, count == 1, count == 2, count == 3);
+                // This fails if the handler interrupted a transaction on the shared bus; the LEDs
+                // then just skip a step.
+                pic.set_leds(count == 0, count == 1, count == 2, count == 3).ok();
                 leds.led1_off();
                 if buttons.button1_pressed() {
                     leds.led0_off();
@@ -93,8 +88,6 @@ fn TIMER1() {
 
         *halfcount = (*halfcount + 1) % 8;
     } else {
-        // unsafe: The SPSC documentation does it too.
-        let mut for_timer1 = unsafe { FOR_TIMER1.split().1 };
-        *stuff = for_timer1.dequeue();
+        *stuff = FOR_TIMER1.take();
     }
 }
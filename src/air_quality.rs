@@ -0,0 +1,150 @@
+//! Compensated air quality readings
+//!
+//! The CCS811's readings drift with the ambient humidity and temperature unless it is told about
+//! them. ``AirQuality`` owns the CCS811 and the Si7021 drivers, and keeps the CCS811's environment
+//! data up to date with fresh Si7021 measurements while delivering readings:
+//!
+//! ```ignore
+//! let mut air = AirQuality::new(ccs, si7021);
+//! air.ccs811().set_drive_mode(&mut wake, DriveMode::Every1s, false)?;
+//! loop {
+//!     let reading = air.next(&mut wake).await?;
+//!     // reading.eco2, reading.tvoc
+//! }
+//! ```
+//!
+//! Both sensors need to be powered, and the CCS811 running its application (see the ``ccs811``
+//! module). Waiting is done with the ``time_driver``, which needs to be initialized.
+
+use crate::ccs811::{self, Ccs811, Reading, WakeLine};
+use crate::rtc::TICK_HZ;
+use crate::si7021::{self, Measurement, Si7021};
+use crate::time_driver::{self, Timer};
+
+/// Default interval between environment updates, in seconds
+///
+/// The ambient conditions indoors change slowly; a Si7021 measurement draws 150uA for about 23ms.
+pub const DEFAULT_INTERVAL_S: u32 = 60;
+
+/// How often to check whether the CCS811 has a new reading
+const READ_POLL_MS: u32 = 250;
+/// Time the Si7021 takes for a humidity measurement at full resolution
+const CONVERSION_MS: u32 = 23;
+/// How often the conversion result is polled for (at 1ms intervals) past the expected time
+const CONVERSION_POLL_LIMIT: u8 = 20;
+
+#[derive(Debug)]
+pub enum Error {
+    Ccs811(ccs811::Error),
+    Si7021(si7021::Error),
+}
+
+impl From<ccs811::Error> for Error {
+    fn from(e: ccs811::Error) -> Self
+    {
+        Error::Ccs811(e)
+    }
+}
+
+impl From<si7021::Error> for Error {
+    fn from(e: si7021::Error) -> Self
+    {
+        Error::Si7021(e)
+    }
+}
+
+pub struct AirQuality<'a> {
+    ccs: Ccs811<'a>,
+    si7021: Si7021<'a>,
+    /// Update interval in ticks
+    interval: u64,
+    /// Tick at which the environment data is due for an update
+    next_update: u64,
+    environment: Option<Measurement>,
+}
+
+impl<'a> AirQuality<'a> {
+    /// Combine the drivers; the first environment update happens with the first reading.
+    pub fn new(ccs: Ccs811<'a>, si7021: Si7021<'a>) -> Self
+    {
+        AirQuality {
+            ccs,
+            si7021,
+            interval: u64::from(DEFAULT_INTERVAL_S) * u64::from(TICK_HZ),
+            next_update: 0,
+            environment: None,
+        }
+    }
+
+    pub fn set_interval(&mut self, seconds: u32)
+    {
+        self.interval = u64::from(seconds) * u64::from(TICK_HZ);
+    }
+
+    /// Access the CCS811 driver, eg. to set its drive mode
+    pub fn ccs811(&mut self) -> &mut Ccs811<'a>
+    {
+        &mut self.ccs
+    }
+
+    /// The conditions last sent to the CCS811
+    pub fn environment(&self) -> Option<Measurement>
+    {
+        self.environment
+    }
+
+    /// Measure humidity and temperature and send them to the CCS811, regardless of whether an
+    /// update is due.
+    pub async fn update(&mut self, wake: &mut impl WakeLine) -> Result<Measurement, Error>
+    {
+        self.si7021.start_humidity()?;
+        Timer::after_ms(CONVERSION_MS).await;
+        let mut attempts = 0;
+        let humidity = loop {
+            match self.si7021.read_humidity() {
+                Ok(humidity) => break humidity,
+                Err(nb::Error::WouldBlock) if attempts < CONVERSION_POLL_LIMIT => {
+                    attempts += 1;
+                    Timer::after_ms(1).await;
+                }
+                Err(nb::Error::WouldBlock) => return Err(si7021::Error::Timeout.into()),
+                Err(nb::Error::Other(e)) => return Err(e.into()),
+            }
+        };
+        let temperature = self.si7021.temperature_of_last_humidity()?;
+
+        self.ccs.set_environment(wake, temperature, humidity)?;
+
+        let measurement = Measurement { temperature, humidity };
+        self.environment = Some(measurement);
+        self.next_update = time_driver::now() + self.interval;
+        Ok(measurement)
+    }
+
+    /// Update the environment data if it is due.
+    pub async fn maintain(&mut self, wake: &mut impl WakeLine) -> Result<(), Error>
+    {
+        if self.environment.is_none() || time_driver::now() >= self.next_update {
+            self.update(wake).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the CCS811's next reading, updating the environment data whenever it is due.
+    pub async fn next(&mut self, wake: &mut impl WakeLine) -> Result<Reading, Error>
+    {
+        loop {
+            self.maintain(wake).await?;
+            match self.ccs.read(wake) {
+                Ok(reading) => return Ok(reading),
+                Err(nb::Error::WouldBlock) => Timer::after_ms(READ_POLL_MS).await,
+                Err(nb::Error::Other(e)) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub fn free(self) -> (Ccs811<'a>, Si7021<'a>)
+    {
+        (self.ccs, self.si7021)
+    }
+}
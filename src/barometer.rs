@@ -0,0 +1,378 @@
+//! Altitude and weather trends from pressure readings
+//!
+//! The ``Altimeter`` turns pressure (as measured by the ``bmp280`` driver) into altitude above a
+//! reference pressure, using the international barometric formula. It is precise to well below a
+//! centimetre, so it can resolve the BMP280's finest pressure steps.
+//!
+//! The ``History`` collects a pressure sample every 10 minutes over three hours, from which it
+//! derives the pressure ``Trend`` and a Zambretti-style ``Forecast``:
+//!
+//! ```ignore
+//! let now = || Seconds((board.rtc.uptime_ms() / 1000) as u32);
+//! let mut history = match board.boot {
+//!     hibernate::Boot::Em4Wake(state) => History::from_words(&state[..HISTORY_WORDS], now(), SLEEP_TIME).unwrap_or_default(),
+//!     hibernate::Boot::Cold => History::new(),
+//! };
+//! let m = bmp.measure(&mut delay)?;
+//! history.record(now(), m.pressure);
+//! let forecast = history.forecast(STATION_ALTITUDE_CM);
+//! state[..HISTORY_WORDS].copy_from_slice(&history.to_words(now()));
+//! hibernate::hibernate(&mut board.pic, &mut board.rtc, hibernate::Wake { button0: false, after: Some(SLEEP_TIME) }, &state);
+//! ```
+//!
+//! The history holds no pointers and fits in a few words, so it can be kept through hibernation
+//! (see ``to_words``) or in any other storage.
+//!
+//! All computations are done in fixed point.
+
+use core::fmt;
+
+use crate::bmp280::Measurement;
+use crate::time::Seconds;
+
+const Q30: i64 = 1 << 30;
+
+/// Exponent 0.190263 (1/5.25588) of the barometric formula, in Q30
+const EXPONENT_Q30: i64 = 204_293_341;
+/// Its inverse, in Q30
+const INVERSE_EXPONENT_Q30: i64 = 5_643_461_020;
+/// Scale height of the barometric formula (44330.77m), in centimetres
+const SCALE_CM: i64 = 4_433_077;
+
+/// Standard atmospheric pressure at sea level, in Pascal
+pub const STANDARD_PRESSURE: u32 = 101_325;
+
+/// Natural logarithm of a/b in Q30, for ratios that are not too far off 1
+fn ln_ratio_q30(a: i64, b: i64) -> i64
+{
+    // ln(a/b) = 2 atanh(z) = 2 (z + z^3/3 + z^5/5 + ...) with z = (a - b) / (a + b)
+    let z = ((a - b) << 30) / (a + b);
+    let z2 = (z * z) >> 30;
+    let (mut sum, mut power, mut k) = (0, z, 1);
+    while power != 0 {
+        sum += power / k;
+        // Dividing rather than shifting, so that negative terms also round towards zero and end
+        // the series
+        power = power * z2 / Q30;
+        k += 2;
+    }
+    2 * sum
+}
+
+/// e^y in Q30, for small Q30 arguments y
+fn exp_q30(y: i64) -> i64
+{
+    let (mut sum, mut term, mut k) = (Q30, Q30, 1);
+    loop {
+        term = term * y / Q30 / k;
+        if term == 0 {
+            return sum;
+        }
+        sum += term;
+        k += 1;
+    }
+}
+
+/// Pressure at altitude zero (eg. sea level) for a pressure measured at the given altitude, both
+/// in 1/256 Pa
+pub fn reference_pressure(pressure_fine: u32, altitude_cm: i32) -> u32
+{
+    // p0 = p / (1 - h / 44330.77m)^5.25588; limited to altitudes where the series converge
+    let h = i64::from(altitude_cm).clamp(-50_000, 900_000);
+    let ln = ln_ratio_q30(SCALE_CM - h, SCALE_CM);
+    let factor = exp_q30(-((ln * INVERSE_EXPONENT_Q30) >> 30));
+    ((i64::from(pressure_fine) * factor) >> 30) as u32
+}
+
+/// Altitude in centimetres at which the pressure is ``pressure_fine``, relative to where it is
+/// ``reference_fine`` (both in 1/256 Pa)
+pub fn altitude_cm(pressure_fine: u32, reference_fine: u32) -> i32
+{
+    // h = 44330.77m * (1 - (p / p0)^0.190263)
+    let ln = ln_ratio_q30(i64::from(pressure_fine.max(1)), i64::from(reference_fine.max(1)));
+    let power = exp_q30((ln * EXPONENT_Q30) >> 30);
+    ((SCALE_CM * (Q30 - power)) >> 30) as i32
+}
+
+/// Altitude computation relative to a configurable reference pressure
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Altimeter {
+    /// In 1/256 Pa
+    reference: u32,
+}
+
+impl Default for Altimeter {
+    fn default() -> Self
+    {
+        Self::new(STANDARD_PRESSURE)
+    }
+}
+
+impl Altimeter {
+    /// Measure altitudes relative to where the pressure is ``reference_pa``.
+    ///
+    /// With the local sea level pressure (QNH) as reference, this gives altitude above sea level;
+    /// with the standard pressure, it gives the pressure altitude used in aviation.
+    pub fn new(reference_pa: u32) -> Self
+    {
+        Altimeter { reference: reference_pa << 8 }
+    }
+
+    /// Reference pressure in Pascal
+    pub fn reference_pa(&self) -> u32
+    {
+        self.reference >> 8
+    }
+
+    pub fn set_reference_pa(&mut self, reference_pa: u32)
+    {
+        self.reference = reference_pa << 8;
+    }
+
+    /// Set the reference so that the measurement corresponds to the given altitude (eg. 0 to
+    /// measure heights relative to the current position).
+    pub fn calibrate(&mut self, measurement: &Measurement, altitude_cm: i32)
+    {
+        self.reference = reference_pressure(measurement.pressure_fine, altitude_cm);
+    }
+
+    pub fn altitude_cm(&self, measurement: &Measurement) -> i32
+    {
+        altitude_cm(measurement.pressure_fine, self.reference)
+    }
+}
+
+/// Interval between history samples
+pub const HISTORY_INTERVAL: Seconds = Seconds(10 * 60);
+/// Number of samples needed to cover three hours
+const SLOTS: usize = 19;
+/// Minimum span of samples for a trend, in intervals (one hour)
+const MIN_TREND_INTERVALS: usize = 6;
+/// Pressure change over three hours (in 0.1 hPa) beyond which the pressure is not steady
+const STEADY_LIMIT: i32 = 16;
+
+/// Number of words ``History::to_words`` produces
+pub const HISTORY_WORDS: usize = 2 + SLOTS.div_ceil(2);
+/// Marks valid history words (in the upper half of the first word)
+const HISTORY_MAGIC: u32 = 0x4853_0000;
+
+/// Pressure tendency over the last three hours
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trend {
+    Falling,
+    Steady,
+    Rising,
+}
+
+/// Rolling three-hour pressure history
+///
+/// Samples are kept in units of 0.1 hPa.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct History {
+    samples: [u16; SLOTS],
+    /// Index of the oldest sample
+    head: u8,
+    len: u8,
+    /// Time the newest sample was recorded
+    last: u32,
+}
+
+impl History {
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    fn push(&mut self, sample: u16)
+    {
+        let index = (usize::from(self.head) + usize::from(self.len)) % SLOTS;
+        self.samples[index] = sample;
+        if usize::from(self.len) < SLOTS {
+            self.len += 1;
+        } else {
+            self.head = ((usize::from(self.head) + 1) % SLOTS) as u8;
+        }
+    }
+
+    /// Samples from oldest to newest
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_
+    {
+        (0..usize::from(self.len)).map(move |i| self.samples[(usize::from(self.head) + i) % SLOTS])
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.len == 0
+    }
+
+    /// Record a pressure reading taken at ``now``, if a sample is due; returns whether it was
+    /// used.
+    ///
+    /// ``now`` can be on any clock that counts seconds (eg. the RTCC's uptime). Samples missed in
+    /// a short gap are interpolated; after a gap of more than three hours, the history starts
+    /// over.
+    pub fn record(&mut self, now: Seconds, pressure_pa: u32) -> bool
+    {
+        let sample = (pressure_pa / 10).min(u32::from(u16::MAX)) as u16;
+        let elapsed = now.0.wrapping_sub(self.last);
+
+        if self.len != 0 && elapsed < HISTORY_INTERVAL.0 {
+            return false;
+        }
+        let missed = (elapsed / HISTORY_INTERVAL.0) as usize;
+        if self.len == 0 || missed > SLOTS {
+            *self = Self::new();
+        } else if let Some(previous) = self.iter().last() {
+            for i in 1..missed {
+                let (from, to) = (i32::from(previous), i32::from(sample));
+                self.push((from + (to - from) * i as i32 / missed as i32) as u16);
+            }
+        }
+        self.push(sample);
+        self.last = now.0;
+        true
+    }
+
+    /// Pressure change over the last three hours in 0.1 hPa, extrapolated from the available
+    /// history if it spans at least an hour
+    pub fn change_3h(&self) -> Option<i32>
+    {
+        let intervals = usize::from(self.len).checked_sub(1)?;
+        if intervals < MIN_TREND_INTERVALS {
+            return None;
+        }
+        let oldest = i32::from(self.iter().next()?);
+        let newest = i32::from(self.iter().last()?);
+        Some((newest - oldest) * (SLOTS as i32 - 1) / intervals as i32)
+    }
+
+    pub fn trend(&self) -> Option<Trend>
+    {
+        Some(match self.change_3h()? {
+            change if change < -STEADY_LIMIT => Trend::Falling,
+            change if change > STEADY_LIMIT => Trend::Rising,
+            _ => Trend::Steady,
+        })
+    }
+
+    /// Forecast from the newest sample and the trend, for a station at the given altitude
+    pub fn forecast(&self, altitude_cm: i32) -> Option<Forecast>
+    {
+        let trend = self.trend()?;
+        let station_pa = u32::from(self.iter().last()?) * 10;
+        let sea_level_pa = reference_pressure(station_pa << 8, altitude_cm) >> 8;
+        Some(Forecast::new(sea_level_pa, trend))
+    }
+
+    /// Serialize the history, eg. into the retention registers through ``hibernate::State``.
+    ///
+    /// As the clock ``record`` uses typically starts over after a reset, the newest sample's time
+    /// is stored relative to ``now``.
+    pub fn to_words(&self, now: Seconds) -> [u32; HISTORY_WORDS]
+    {
+        let mut words = [0; HISTORY_WORDS];
+        words[0] = HISTORY_MAGIC | (u32::from(self.len) << 8) | u32::from(self.head);
+        words[1] = now.0.wrapping_sub(self.last);
+        for (i, sample) in self.samples.iter().enumerate() {
+            words[2 + i / 2] |= u32::from(*sample) << (16 * (i % 2));
+        }
+        words
+    }
+
+    /// Restore a history from ``to_words`` output; returns None if the words do not hold one.
+    ///
+    /// ``now`` is the time on the clock that is used with ``record`` from here on, and ``gap`` is
+    /// the time that passed between ``to_words`` and this call (eg. the hibernation time).
+    pub fn from_words(words: &[u32], now: Seconds, gap: Seconds) -> Option<Self>
+    {
+        if words.len() < HISTORY_WORDS || words[0] & 0xffff_0000 != HISTORY_MAGIC {
+            return None;
+        }
+        let (len, head) = ((words[0] >> 8) as u8, words[0] as u8);
+        if usize::from(len) > SLOTS || usize::from(head) >= SLOTS {
+            return None;
+        }
+        let mut samples = [0; SLOTS];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = (words[2 + i / 2] >> (16 * (i % 2))) as u16;
+        }
+        let last = now.0.wrapping_sub(gap.0).wrapping_sub(words[1]);
+        Some(History { samples, head, len, last })
+    }
+}
+
+/// Forecast texts by Zambretti number, 1-9 for falling, 10-19 for steady and 20-32 for rising
+/// pressure
+const FORECASTS: [&str; 32] = [
+    "Settled fine",
+    "Fine weather",
+    "Fine, becoming less settled",
+    "Fairly fine, showery later",
+    "Showery, becoming more unsettled",
+    "Unsettled, rain later",
+    "Rain at times, worse later",
+    "Rain at times, becoming very unsettled",
+    "Very unsettled, rain",
+    "Settled fine",
+    "Fine weather",
+    "Fine, possibly showers",
+    "Fairly fine, showers likely",
+    "Showery, bright intervals",
+    "Changeable, some rain",
+    "Unsettled, rain at times",
+    "Rain at frequent intervals",
+    "Very unsettled, rain",
+    "Stormy, much rain",
+    "Settled fine",
+    "Fine weather",
+    "Becoming fine",
+    "Fairly fine, improving",
+    "Fairly fine, possibly showers early",
+    "Showery early, improving",
+    "Changeable, mending",
+    "Rather unsettled, clearing later",
+    "Unsettled, probably improving",
+    "Unsettled, short fine intervals",
+    "Very unsettled, finer at times",
+    "Stormy, possibly improving",
+    "Stormy, much rain",
+];
+
+/// A Zambretti forecast, for the next few hours
+///
+/// This is the simplified form of the algorithm that ignores wind direction and season.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Forecast(u8);
+
+impl Forecast {
+    /// Forecast from the sea level pressure and its trend
+    pub fn new(sea_level_pa: u32, trend: Trend) -> Self
+    {
+        // Z = a - b * P (with P in hPa), limited to the trend's range of numbers
+        let (a, b, min, max) = match trend {
+            Trend::Falling => (127, 12, 1, 9),
+            Trend::Steady => (144, 13, 10, 19),
+            Trend::Rising => (185, 16, 20, 32),
+        };
+        let z = (a * 10_000 - b * sea_level_pa as i64 + 5_000).div_euclid(10_000);
+        Forecast(z.clamp(min, max) as u8)
+    }
+
+    /// The Zambretti number, from 1 to 32
+    pub fn number(&self) -> u8
+    {
+        self.0
+    }
+
+    pub fn description(&self) -> &'static str
+    {
+        FORECASTS[usize::from(self.0) - 1]
+    }
+}
+
+impl fmt::Display for Forecast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        f.write_str(self.description())
+    }
+}
@@ -0,0 +1,220 @@
+//! Supply voltage monitoring
+//!
+//! The board is often powered from a CR2032 coin cell, whose voltage is the best indication of
+//! how much longer it will last. The ``BatteryMonitor`` measures the supply voltages with ADC0
+//! against its internal reference, estimates the remaining capacity from a typical CR2032
+//! discharge curve, and reports when the voltage drops below configurable thresholds:
+//!
+//! ```ignore
+//! let mut battery = board.battery;
+//! battery.set_thresholds(Thresholds { low_mv: 2700, critical_mv: 2400 });
+//! loop {
+//!     match battery.check() {
+//!         Some(Event::Low) => warn_user(),
+//!         Some(Event::Critical) => hibernate(...),
+//!         _ => (),
+//!     }
+//!     ...
+//! }
+//! ```
+//!
+//! A coin cell's voltage sags under load, so readings taken while the LEDs or the CCS811 are on
+//! come out lower than idle ones; the estimate is for readings under light load.
+
+use efr32xg1 as registers;
+
+/// ADC input selection values for the supplies (the PAC has no names for them)
+const POSSEL_AVDD: u8 = 0xe0;
+const POSSEL_DVDD: u8 = 0xe2;
+const NEGSEL_VSS: u8 = 0xff;
+
+/// Full scale of the 5V reference, in millivolts, and of the 12-bit conversion result
+const FULL_SCALE_MV: u32 = 5000;
+const FULL_SCALE: u32 = 4096;
+
+/// Conversions averaged per reading
+const SAMPLES: u32 = 4;
+
+/// Hysteresis for leaving a low battery level, so that a voltage hovering around a threshold
+/// does not cause a stream of events
+const HYSTERESIS_MV: u16 = 50;
+
+/// Remaining capacity of a CR2032 at a given voltage under light load, as (millivolts, percent),
+/// highest voltage first
+const CR2032_CURVE: [(u16, u8); 8] = [
+    (3000, 100),
+    (2900, 80),
+    (2800, 60),
+    (2700, 40),
+    (2600, 20),
+    (2500, 10),
+    (2400, 5),
+    (2000, 0),
+];
+
+/// Supply rails that can be measured
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Supply {
+    /// The analog supply, which on the board is the battery voltage
+    Avdd,
+    /// The digital supply
+    Dvdd,
+}
+
+/// Voltages below which the battery is considered low or critical
+#[derive(Clone, Copy, Debug)]
+pub struct Thresholds {
+    pub low_mv: u16,
+    pub critical_mv: u16,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self
+    {
+        Thresholds { low_mv: 2600, critical_mv: 2400 }
+    }
+}
+
+/// Battery state as classified by the thresholds
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Ok,
+    Low,
+    Critical,
+}
+
+/// Changes of the battery level reported by ``BatteryMonitor::check``
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The voltage dropped below the low threshold
+    Low,
+    /// The voltage dropped below the critical threshold
+    Critical,
+    /// The voltage rose above the low threshold again (eg. after replacing the battery)
+    Recovered,
+}
+
+/// Estimate the remaining capacity of a CR2032 from its voltage, in percent.
+pub fn cr2032_capacity_percent(mv: u16) -> u8
+{
+    let (top_mv, top_percent) = CR2032_CURVE[0];
+    if mv >= top_mv {
+        return top_percent;
+    }
+    for pair in CR2032_CURVE.windows(2) {
+        let ((high_mv, high_percent), (low_mv, low_percent)) = (pair[0], pair[1]);
+        if mv >= low_mv {
+            let span = u32::from(high_percent - low_percent);
+            let above = u32::from(mv - low_mv);
+            return low_percent + (above * span / u32::from(high_mv - low_mv)) as u8;
+        }
+    }
+    0
+}
+
+pub struct BatteryMonitor {
+    register: registers::ADC0,
+    thresholds: Thresholds,
+    level: Level,
+}
+
+impl BatteryMonitor {
+    pub fn new(register: registers::ADC0) -> Self
+    {
+        // UNSAFE FIXME as with the HAL's clock enable functions: this is a read-modify-write on a
+        // register that is shared with the HAL's peripheral clocks.
+        let cmu = unsafe { &*registers::CMU::ptr() };
+        cortex_m::interrupt::free(|_| {
+            cmu.hfperclken0.modify(|_, w| w.adc0().set_bit());
+        });
+
+        register.ctrl.write(|w| unsafe { w
+            // FIXME: Assumes the default 19MHz HFPERCLK like the rest of the crate; the ADC
+            // clock is that divided by 2, and the time base counts to 1us.
+            .presc().bits(1)
+            .timebase().bits(19)
+            .warmupmode().normal()
+            });
+
+        BatteryMonitor { register, thresholds: Thresholds::default(), level: Level::Ok }
+    }
+
+    /// Measure a supply voltage in millivolts.
+    pub fn read_mv(&mut self, supply: Supply) -> u16
+    {
+        let possel = match supply {
+            Supply::Avdd => POSSEL_AVDD,
+            Supply::Dvdd => POSSEL_DVDD,
+        };
+        self.register.singlectrl.write(|w| unsafe { w
+            .ref_()._5v()
+            .res()._12bit()
+            .possel().bits(possel)
+            .negsel().bits(NEGSEL_VSS)
+            .at()._16cycles()
+            });
+
+        let mut sum = 0;
+        for _ in 0..SAMPLES {
+            self.register.cmd.write(|w| w.singlestart().set_bit());
+            while self.register.status.read().singledv().bit_is_clear() {}
+            sum += self.register.singledata.read().data().bits() & 0xfff;
+        }
+
+        (sum * FULL_SCALE_MV / (SAMPLES * FULL_SCALE)) as u16
+    }
+
+    /// Measure the battery (ie. AVDD) voltage in millivolts.
+    pub fn battery_mv(&mut self) -> u16
+    {
+        self.read_mv(Supply::Avdd)
+    }
+
+    /// Estimate the remaining battery capacity in percent, assuming a CR2032.
+    pub fn capacity_percent(&mut self) -> u8
+    {
+        cr2032_capacity_percent(self.battery_mv())
+    }
+
+    pub fn set_thresholds(&mut self, thresholds: Thresholds)
+    {
+        self.thresholds = thresholds;
+    }
+
+    /// Battery level as of the last ``check``
+    pub fn level(&self) -> Level
+    {
+        self.level
+    }
+
+    /// Measure the battery, and report if its level changed since the last check.
+    pub fn check(&mut self) -> Option<Event>
+    {
+        let mv = self.battery_mv();
+        let t = self.thresholds;
+
+        let new = match self.level {
+            _ if mv < t.critical_mv => Level::Critical,
+            // Only leave a level once the voltage is clearly above its threshold
+            Level::Critical if mv < t.critical_mv.saturating_add(HYSTERESIS_MV) => Level::Critical,
+            _ if mv < t.low_mv => Level::Low,
+            Level::Low | Level::Critical if mv < t.low_mv.saturating_add(HYSTERESIS_MV) => Level::Low,
+            _ => Level::Ok,
+        };
+
+        let old = core::mem::replace(&mut self.level, new);
+        match new {
+            _ if new == old => None,
+            Level::Critical => Some(Event::Critical),
+            // Coming back from critical to low is not worth an event
+            Level::Low if old == Level::Ok => Some(Event::Low),
+            Level::Low => None,
+            Level::Ok => Some(Event::Recovered),
+        }
+    }
+
+    pub fn free(self) -> registers::ADC0
+    {
+        self.register
+    }
+}
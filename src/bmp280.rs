@@ -0,0 +1,350 @@
+//! Driver for the BMP280 barometric pressure and temperature sensor
+//!
+//! The sensor sits at address 0x77 of the board's I2C bus, and is powered with the environmental
+//! sensor group (``PIC::set_env_sensor``), after which it needs 2ms to start up.
+//!
+//! ``init`` checks the chip ID and reads the factory calibration, which the datasheet's integer
+//! compensation formulas need to turn the raw readings into temperature and pressure.
+//!
+//! Measurements are either triggered one at a time (forced mode, ``measure``), or run continuously
+//! by the sensor with a standby time in between (normal mode, ``start_normal`` and ``read``).
+//! Oversampling and the IIR filter are configured through ``Config``.
+//!
+//! Results are given in centi-degrees Celsius and Pascal.
+
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::i2c_bus::{self, I2cProxy};
+
+/// Address of the sensor on the board (with its SDO pin high)
+pub const ADDR: u8 = 0x77;
+/// Address of the sensor with its SDO pin low
+pub const ADDR_ALTERNATE: u8 = 0x76;
+/// Content of the ID register
+pub const CHIP_ID: u8 = 0x58;
+
+const REG_CALIBRATION: u8 = 0x88;
+const REG_ID: u8 = 0xd0;
+const REG_RESET: u8 = 0xe0;
+const REG_STATUS: u8 = 0xf3;
+const REG_CTRL_MEAS: u8 = 0xf4;
+const REG_CONFIG: u8 = 0xf5;
+const REG_PRESS_MSB: u8 = 0xf7;
+
+const RESET_VALUE: u8 = 0xb6;
+
+const STATUS_MEASURING: u8 = 0x08;
+const STATUS_IM_UPDATE: u8 = 0x01;
+
+const MODE_SLEEP: u8 = 0x00;
+const MODE_FORCED: u8 = 0x01;
+const MODE_NORMAL: u8 = 0x03;
+
+/// Raw pressure reading when the pressure measurement is skipped
+const SKIPPED: i32 = 0x80000;
+
+/// How often a forced measurement polls for its result (at 1ms intervals) after its expected
+/// duration, before giving up
+const POLL_LIMIT: u8 = 10;
+
+#[derive(Debug)]
+pub enum Error {
+    I2c(i2c_bus::Error),
+    /// The device did not identify as a BMP280, but with the given ID
+    WrongChip(u8),
+    /// A forced measurement did not complete in time
+    Timeout,
+}
+
+impl From<i2c_bus::Error> for Error {
+    fn from(e: i2c_bus::Error) -> Self
+    {
+        Error::I2c(e)
+    }
+}
+
+/// Number of samples averaged for a measurement
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Oversampling {
+    Skipped = 0,
+    X1 = 1,
+    X2 = 2,
+    X4 = 3,
+    X8 = 4,
+    X16 = 5,
+}
+
+impl Oversampling {
+    fn samples(self) -> u32
+    {
+        match self {
+            Oversampling::Skipped => 0,
+            _ => 1 << (self as u32 - 1),
+        }
+    }
+}
+
+/// IIR filter coefficient, which smoothes out short disturbances (eg. a slammed door)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Off = 0,
+    X2 = 1,
+    X4 = 2,
+    X8 = 3,
+    X16 = 4,
+}
+
+/// Time between measurements in normal mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Standby {
+    Ms0_5 = 0,
+    Ms62_5 = 1,
+    Ms125 = 2,
+    Ms250 = 3,
+    Ms500 = 4,
+    Ms1000 = 5,
+    Ms2000 = 6,
+    Ms4000 = 7,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub temperature: Oversampling,
+    pub pressure: Oversampling,
+    pub filter: Filter,
+    /// Only used in normal mode
+    pub standby: Standby,
+}
+
+impl Default for Config {
+    /// The datasheet's recommendation for handheld devices
+    fn default() -> Self
+    {
+        Config { temperature: Oversampling::X2, pressure: Oversampling::X16, filter: Filter::X4, standby: Standby::Ms62_5 }
+    }
+}
+
+impl Config {
+    fn ctrl_meas(&self, mode: u8) -> u8
+    {
+        ((self.temperature as u8) << 5) | ((self.pressure as u8) << 2) | mode
+    }
+
+    fn config(&self) -> u8
+    {
+        ((self.standby as u8) << 5) | ((self.filter as u8) << 2)
+    }
+
+    /// Maximum duration of a single measurement, in microseconds
+    pub fn measurement_time_us(&self) -> u32
+    {
+        let pressure = match self.pressure.samples() {
+            0 => 0,
+            n => 2300 * n + 575,
+        };
+        1250 + 2300 * self.temperature.samples() + pressure
+    }
+}
+
+/// Factory calibration values ("trimming parameters")
+#[derive(Clone, Copy, Debug, Default)]
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+}
+
+impl Calibration {
+    fn from_bytes(b: &[u8; 24]) -> Self
+    {
+        let u = |i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+        let s = |i: usize| i16::from_le_bytes([b[i], b[i + 1]]);
+        Calibration {
+            t1: u(0), t2: s(2), t3: s(4),
+            p1: u(6), p2: s(8), p3: s(10), p4: s(12), p5: s(14), p6: s(16), p7: s(18), p8: s(20), p9: s(22),
+        }
+    }
+
+    /// The datasheet's temperature compensation; returns the centi-degrees and the "t_fine" value
+    /// the pressure compensation needs.
+    fn temperature(&self, adc: i32) -> (i32, i32)
+    {
+        let t1 = i32::from(self.t1);
+        let var1 = (((adc >> 3) - (t1 << 1)) * i32::from(self.t2)) >> 11;
+        let var2 = (((((adc >> 4) - t1) * ((adc >> 4) - t1)) >> 12) * i32::from(self.t3)) >> 14;
+        let t_fine = var1 + var2;
+        ((t_fine * 5 + 128) >> 8, t_fine)
+    }
+
+    /// The datasheet's 64-bit pressure compensation, in 1/256 Pa
+    fn pressure(&self, adc: i32, t_fine: i32) -> u32
+    {
+        let mut var1 = i64::from(t_fine) - 128_000;
+        let mut var2 = var1 * var1 * i64::from(self.p6);
+        var2 += (var1 * i64::from(self.p5)) << 17;
+        var2 += i64::from(self.p4) << 35;
+        var1 = ((var1 * var1 * i64::from(self.p3)) >> 8) + ((var1 * i64::from(self.p2)) << 12);
+        var1 = (((1i64 << 47) + var1) * i64::from(self.p1)) >> 33;
+        if var1 == 0 {
+            // Avoid a division by zero (which only happens with broken calibration data)
+            return 0;
+        }
+        let mut p = 1_048_576 - i64::from(adc);
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (i64::from(self.p9) * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (i64::from(self.p8) * p) >> 19;
+        (((p + var1 + var2) >> 8) + (i64::from(self.p7) << 4)) as u32
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    /// Temperature in centi-degrees Celsius
+    pub temperature: i32,
+    /// Pressure in Pascal (0 if the pressure measurement is skipped)
+    pub pressure: u32,
+    /// Pressure in 1/256 Pascal, at the full resolution of the compensation
+    pub pressure_fine: u32,
+}
+
+pub struct Bmp280<'a> {
+    i2c: I2cProxy<'a>,
+    address: u8,
+    calibration: Calibration,
+    config: Config,
+}
+
+impl<'a> Bmp280<'a> {
+    /// Create a driver for the sensor at the board's address; see ``init``.
+    pub fn new(i2c: I2cProxy<'a>) -> Self
+    {
+        Self::with_address(i2c, ADDR)
+    }
+
+    pub fn with_address(i2c: I2cProxy<'a>, address: u8) -> Self
+    {
+        Bmp280 { i2c, address, calibration: Calibration::default(), config: Config::default() }
+    }
+
+    fn read_registers(&mut self, register: u8, buf: &mut [u8]) -> Result<(), Error>
+    {
+        Ok(self.i2c.write_read(self.address, &[register], buf)?)
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), Error>
+    {
+        Ok(self.i2c.write(self.address, &[register, value])?)
+    }
+
+    pub fn chip_id(&mut self) -> Result<u8, Error>
+    {
+        let mut buf = [0];
+        self.read_registers(REG_ID, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    /// Check the chip ID, reset the sensor, read its calibration and apply the configuration.
+    pub fn init(&mut self, delay: &mut impl DelayMs<u8>) -> Result<(), Error>
+    {
+        match self.chip_id()? {
+            CHIP_ID => (),
+            other => return Err(Error::WrongChip(other)),
+        }
+
+        self.write_register(REG_RESET, RESET_VALUE)?;
+        delay.delay_ms(2);
+        // The calibration is copied from the NVM after the reset
+        let mut status = [STATUS_IM_UPDATE];
+        for _ in 0..POLL_LIMIT {
+            self.read_registers(REG_STATUS, &mut status)?;
+            if status[0] & STATUS_IM_UPDATE == 0 {
+                break;
+            }
+            delay.delay_ms(1);
+        }
+
+        let mut calibration = [0; 24];
+        self.read_registers(REG_CALIBRATION, &mut calibration)?;
+        self.calibration = Calibration::from_bytes(&calibration);
+
+        let config = self.config;
+        self.set_config(config)
+    }
+
+    pub fn config(&self) -> Config
+    {
+        self.config
+    }
+
+    /// Apply a configuration, putting the sensor to sleep (the configuration register may be
+    /// ignored in normal mode).
+    pub fn set_config(&mut self, config: Config) -> Result<(), Error>
+    {
+        self.config = config;
+        self.write_register(REG_CTRL_MEAS, config.ctrl_meas(MODE_SLEEP))?;
+        self.write_register(REG_CONFIG, config.config())
+    }
+
+    /// Take a single measurement in forced mode, waiting for the result.
+    ///
+    /// The sensor goes back to sleep afterwards.
+    pub fn measure(&mut self, delay: &mut impl DelayMs<u8>) -> Result<Measurement, Error>
+    {
+        let ctrl_meas = self.config.ctrl_meas(MODE_FORCED);
+        self.write_register(REG_CTRL_MEAS, ctrl_meas)?;
+        delay.delay_ms(self.config.measurement_time_us().div_ceil(1000) as u8);
+
+        let mut status = [STATUS_MEASURING];
+        for _ in 0..POLL_LIMIT {
+            self.read_registers(REG_STATUS, &mut status)?;
+            if status[0] & STATUS_MEASURING == 0 {
+                return self.read();
+            }
+            delay.delay_ms(1);
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Start measuring continuously in normal mode.
+    pub fn start_normal(&mut self) -> Result<(), Error>
+    {
+        let ctrl_meas = self.config.ctrl_meas(MODE_NORMAL);
+        self.write_register(REG_CTRL_MEAS, ctrl_meas)
+    }
+
+    /// Stop normal mode measurements.
+    pub fn sleep(&mut self) -> Result<(), Error>
+    {
+        let ctrl_meas = self.config.ctrl_meas(MODE_SLEEP);
+        self.write_register(REG_CTRL_MEAS, ctrl_meas)
+    }
+
+    /// Read the most recent measurement results.
+    pub fn read(&mut self) -> Result<Measurement, Error>
+    {
+        // Pressure and temperature are read in one burst, so they are from the same measurement
+        let mut buf = [0; 6];
+        self.read_registers(REG_PRESS_MSB, &mut buf)?;
+        let raw = |b: &[u8]| (i32::from(b[0]) << 12) | (i32::from(b[1]) << 4) | (i32::from(b[2]) >> 4);
+        let (adc_p, adc_t) = (raw(&buf[0..3]), raw(&buf[3..6]));
+
+        let (temperature, t_fine) = self.calibration.temperature(adc_t);
+        let pressure_fine = if adc_p == SKIPPED { 0 } else { self.calibration.pressure(adc_p, t_fine) };
+        Ok(Measurement { temperature, pressure: pressure_fine >> 8, pressure_fine })
+    }
+
+    pub fn free(self) -> I2cProxy<'a>
+    {
+        self.i2c
+    }
+}
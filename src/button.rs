@@ -1,12 +1,25 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
 use efm32gg_hal::gpio;
 use embedded_hal::digital::InputPin;
 use efm32gg_hal::gpio::EFM32Pin;
 
+use crate::exti;
+
 pub struct Buttons {
     button0: gpio::pins::PD14<gpio::Input>,
     button1: gpio::pins::PD15<gpio::Input>,
 }
 
+/// Identifies one of the two user buttons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Button0,
+    Button1,
+}
+
 /// A representation of the two user buttons on the STK3700
 impl Buttons {
     pub fn new(pd14: gpio::pins::PD14<gpio::Disabled>, pd15: gpio::pins::PD15<gpio::Disabled>) -> Self {
@@ -24,4 +37,58 @@ impl Buttons {
     {
         self.button1.is_low()
     }
+
+    /// Wait until either of the buttons gets pressed, and report which one it was.
+    ///
+    /// This only completes on a press that happens after the first poll; a button that is held
+    /// down already does not count. It needs the GPIO interrupts to be set up as described in
+    /// the ``exti`` module.
+    pub fn wait_for_press(&mut self) -> WaitForPress<'_>
+    {
+        WaitForPress { _buttons: self, armed: false }
+    }
+}
+
+/// Future returned by ``Buttons::wait_for_press``
+pub struct WaitForPress<'a> {
+    // Held only to keep anyone else from arming the same lines
+    _buttons: &'a mut Buttons,
+    armed: bool,
+}
+
+impl Future for WaitForPress<'_> {
+    type Output = Button;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Button>
+    {
+        if !self.armed {
+            exti::configure();
+            exti::arm(exti::LINE_BUTTON0);
+            exti::arm(exti::LINE_BUTTON1);
+            self.armed = true;
+        }
+
+        let result = if exti::poll_fired(exti::LINE_BUTTON0, cx.waker()) {
+            Button::Button0
+        } else if exti::poll_fired(exti::LINE_BUTTON1, cx.waker()) {
+            Button::Button1
+        } else {
+            return Poll::Pending;
+        };
+
+        exti::disarm(exti::LINE_BUTTON0);
+        exti::disarm(exti::LINE_BUTTON1);
+        self.armed = false;
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for WaitForPress<'_> {
+    fn drop(&mut self)
+    {
+        if self.armed {
+            exti::disarm(exti::LINE_BUTTON0);
+            exti::disarm(exti::LINE_BUTTON1);
+        }
+    }
 }
@@ -0,0 +1,388 @@
+//! Driver for the CCS811 indoor air quality sensor
+//!
+//! The sensor sits at address 0x5a of the board's I2C bus. It is powered through the PIC
+//! (``PIC::set_ccs``, or the ``Ccs`` domain of a ``PowerManager``), after which it needs 20ms to
+//! boot. It only listens on the bus while its nWAKE line is asserted, which the PIC drives as well;
+//! as an asserted nWAKE keeps the sensor's interface powered, the driver asserts it only around
+//! its transactions. All methods therefore take the ``WakeLine`` to use, which is implemented by
+//! ``ManagedWake`` for applications that manage power, and by the ``PIC`` itself for those that
+//! switch the sensor directly:
+//!
+//! ```ignore
+//! let lease = power.acquire(Domain::Ccs)?;
+//! delay.delay_ms(20u8);
+//! let mut ccs = Ccs811::new(board.i2c_bus.acquire());
+//! let mut wake = ManagedWake::new(&mut power, &lease);
+//! ccs.start_app(&mut wake, &mut delay)?;
+//! ccs.set_drive_mode(&mut wake, DriveMode::Every1s, false)?;
+//! // ... once a second:
+//! if let Ok(reading) = ccs.read(&mut wake) {
+//!     // reading.eco2, reading.tvoc
+//! }
+//! ```
+//!
+//! After power-up, the sensor runs a boot loader; ``start_app`` switches it over to the
+//! measurement application. Its readings take about 20 minutes to settle after each start (and
+//! are only accurate after 48 hours of initial burn-in), and drift unless they are compensated for
+//! the ambient humidity and temperature.
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+use crate::i2c_bus::{self, I2cProxy};
+use crate::pic::PIC;
+use crate::power::{Lease, PowerManager};
+
+pub const ADDR: u8 = 0x5a;
+pub const HW_ID: u8 = 0x81;
+
+const STATUS: u8 = 0x00;
+const MEAS_MODE: u8 = 0x01;
+const ALG_RESULT_DATA: u8 = 0x02;
+const ENV_DATA: u8 = 0x05;
+const REG_HW_ID: u8 = 0x20;
+const ERROR_ID: u8 = 0xe0;
+const APP_START: u8 = 0xf4;
+const SW_RESET: [u8; 5] = [0xff, 0x11, 0xe5, 0x72, 0x8a];
+
+/// Data ready interrupt enable in MEAS_MODE
+const MEAS_MODE_INT_DATARDY: u8 = 0x08;
+
+/// Time from power-up or reset until the boot loader responds
+const BOOT_MS: u8 = 20;
+/// Time the application needs to start (the datasheet gives 1ms)
+const APP_START_MS: u8 = 2;
+
+/// Something driving the CCS811's nWAKE line
+///
+/// Asserting nWAKE takes an I2C transaction with the PIC, which is longer than the 50us the sensor
+/// needs to wake up, so no further delays are needed.
+pub trait WakeLine {
+    /// Assert (``true``) or release the wake line
+    fn set_wake(&mut self, wake: bool) -> Result<(), i2c_bus::Error>;
+}
+
+/// Driving the wake line through the PIC directly always keeps the sensor powered (as
+/// ``set_ccs(true, wake)`` does): Used on a sensor that was switched off, it powers it up, without
+/// the boot delay the sensor then needs. Applications that use a ``PowerManager`` go through
+/// ``ManagedWake`` instead.
+impl<D, I> WakeLine for PIC<D, I>
+    where D: DelayUs<u16>,
+          I: Write<Error = i2c_bus::Error> + Read<Error = i2c_bus::Error>,
+{
+    fn set_wake(&mut self, wake: bool) -> Result<(), i2c_bus::Error>
+    {
+        self.set_ccs(true, wake)
+    }
+}
+
+/// The wake line of a CCS811 powered through a ``PowerManager``
+pub struct ManagedWake<'m, D, I> {
+    power: &'m mut PowerManager<D, I>,
+    lease: &'m Lease,
+}
+
+impl<'m, D, I> ManagedWake<'m, D, I> {
+    /// Use the wake line of the ``Ccs`` domain, which the lease must be for.
+    pub fn new(power: &'m mut PowerManager<D, I>, lease: &'m Lease) -> Self
+    {
+        ManagedWake { power, lease }
+    }
+}
+
+impl<D, I> WakeLine for ManagedWake<'_, D, I>
+    where D: DelayUs<u16>,
+          I: Write<Error = i2c_bus::Error> + Read<Error = i2c_bus::Error>,
+{
+    fn set_wake(&mut self, wake: bool) -> Result<(), i2c_bus::Error>
+    {
+        self.power.set_ccs_wake(self.lease, wake)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    I2c(i2c_bus::Error),
+    /// Driving the wake line through the PIC failed
+    Wake(i2c_bus::Error),
+    /// The device at the address reported a different hardware ID
+    WrongChip(u8),
+    /// The boot loader has no valid application to start
+    NoApplication,
+    /// The sensor flagged an error
+    Sensor(ErrorId),
+}
+
+impl From<i2c_bus::Error> for Error {
+    fn from(e: i2c_bus::Error) -> Self
+    {
+        Error::I2c(e)
+    }
+}
+
+/// Contents of the STATUS register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Status(pub u8);
+
+impl Status {
+    /// The measurement application is running (as opposed to the boot loader)
+    pub fn app_running(self) -> bool
+    {
+        self.0 & 0x80 != 0
+    }
+
+    /// The boot loader found a valid application
+    pub fn app_valid(self) -> bool
+    {
+        self.0 & 0x10 != 0
+    }
+
+    /// A new reading is available
+    pub fn data_ready(self) -> bool
+    {
+        self.0 & 0x08 != 0
+    }
+
+    /// An error occurred, which is described in the ERROR_ID register
+    pub fn error(self) -> bool
+    {
+        self.0 & 0x01 != 0
+    }
+}
+
+/// Contents of the ERROR_ID register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ErrorId(pub u8);
+
+impl ErrorId {
+    /// A write went to an invalid register
+    pub fn write_reg_invalid(self) -> bool
+    {
+        self.0 & 0x01 != 0
+    }
+
+    /// A read went to an invalid register
+    pub fn read_reg_invalid(self) -> bool
+    {
+        self.0 & 0x02 != 0
+    }
+
+    /// An unsupported drive mode was requested
+    pub fn measmode_invalid(self) -> bool
+    {
+        self.0 & 0x04 != 0
+    }
+
+    /// The sensor resistance reached its maximum
+    pub fn max_resistance(self) -> bool
+    {
+        self.0 & 0x08 != 0
+    }
+
+    /// The heater current is out of range
+    pub fn heater_fault(self) -> bool
+    {
+        self.0 & 0x10 != 0
+    }
+
+    /// The heater voltage is out of range
+    pub fn heater_supply(self) -> bool
+    {
+        self.0 & 0x20 != 0
+    }
+}
+
+/// How often the sensor measures
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DriveMode {
+    /// No measurements (the lowest power mode)
+    Idle,
+    Every1s,
+    Every10s,
+    Every60s,
+    /// Every 250ms, with only the raw data updated
+    Every250ms,
+}
+
+impl DriveMode {
+    fn to_bits(self) -> u8
+    {
+        let mode = match self {
+            DriveMode::Idle => 0,
+            DriveMode::Every1s => 1,
+            DriveMode::Every10s => 2,
+            DriveMode::Every60s => 3,
+            DriveMode::Every250ms => 4,
+        };
+        mode << 4
+    }
+}
+
+/// Result of a measurement
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    /// Equivalent CO2 concentration in ppm (400 to 8192)
+    pub eco2: u16,
+    /// Total volatile organic compounds in ppb (0 to 1187)
+    pub tvoc: u16,
+    /// Current through the sensor in microamperes
+    pub current: u8,
+    /// Voltage across the sensor, in units of 1.65V/1023
+    pub raw: u16,
+}
+
+/// Encode ambient conditions for the ENV_DATA register
+///
+/// Temperature is given in centi-degrees Celsius and relative humidity in per-mille (as produced by
+/// the ``si7021`` driver). The register holds the humidity in 1/512 %, and the temperature in
+/// 1/512 °C offset by 25°C, both as big-endian 16-bit values; temperatures outside -25°C to about
+/// 100°C are clamped.
+pub fn env_data(temperature: i32, humidity: u16) -> [u8; 4]
+{
+    let humidity = (u32::from(humidity.min(1000)) * 256 + 2) / 5;
+    let temperature = (((i64::from(temperature) + 2500).max(0) * 128 + 12) / 25).min(0xffff);
+    let [h1, h0] = (humidity as u16).to_be_bytes();
+    let [t1, t0] = (temperature as u16).to_be_bytes();
+    [h1, h0, t1, t0]
+}
+
+pub struct Ccs811<'a> {
+    i2c: I2cProxy<'a>,
+}
+
+impl<'a> Ccs811<'a> {
+    pub fn new(i2c: I2cProxy<'a>) -> Self
+    {
+        Ccs811 { i2c }
+    }
+
+    /// Run a series of transfers with the wake line asserted.
+    fn awake<W: WakeLine, T>(&mut self, wake: &mut W, f: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error>
+    {
+        wake.set_wake(true).map_err(Error::Wake)?;
+        let result = f(self);
+        // Release the line even after a failed transfer, but report the transfer's error first
+        let released = wake.set_wake(false).map_err(Error::Wake);
+        let value = result?;
+        released?;
+        Ok(value)
+    }
+
+    fn read_register<const N: usize>(&mut self, register: u8) -> Result<[u8; N], Error>
+    {
+        let mut buf = [0; N];
+        self.i2c.write_read(ADDR, &[register], &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Turn a set error flag into an error (which clears the flag).
+    fn check(&mut self, status: Status) -> Result<Status, Error>
+    {
+        if status.error() {
+            let [id] = self.read_register(ERROR_ID)?;
+            return Err(Error::Sensor(ErrorId(id)));
+        }
+        Ok(status)
+    }
+
+    pub fn hardware_id(&mut self, wake: &mut impl WakeLine) -> Result<u8, Error>
+    {
+        self.awake(wake, |s| Ok(s.read_register::<1>(REG_HW_ID)?[0]))
+    }
+
+    pub fn status(&mut self, wake: &mut impl WakeLine) -> Result<Status, Error>
+    {
+        self.awake(wake, |s| Ok(Status(s.read_register::<1>(STATUS)?[0])))
+    }
+
+    /// Read (and thereby clear) the error flags
+    pub fn error_id(&mut self, wake: &mut impl WakeLine) -> Result<ErrorId, Error>
+    {
+        self.awake(wake, |s| Ok(ErrorId(s.read_register::<1>(ERROR_ID)?[0])))
+    }
+
+    /// Switch from the boot loader to the measurement application.
+    ///
+    /// The sensor starts out idle (``DriveMode::Idle``). If the application is already running
+    /// (eg. because the sensor stayed powered while the MCU was reset), this only checks for
+    /// errors.
+    pub fn start_app(&mut self, wake: &mut impl WakeLine, delay: &mut impl DelayMs<u8>) -> Result<(), Error>
+    {
+        self.awake(wake, |s| {
+            let [id] = s.read_register(REG_HW_ID)?;
+            if id != HW_ID {
+                return Err(Error::WrongChip(id));
+            }
+
+            let status = Status(s.read_register::<1>(STATUS)?[0]);
+            if !status.app_running() {
+                if !status.app_valid() {
+                    return Err(Error::NoApplication);
+                }
+                s.i2c.write(ADDR, &[APP_START])?;
+                delay.delay_ms(APP_START_MS);
+            }
+
+            let status = Status(s.read_register::<1>(STATUS)?[0]);
+            let status = s.check(status)?;
+            if !status.app_running() {
+                return Err(Error::NoApplication);
+            }
+            Ok(())
+        })
+    }
+
+    /// Set how often the sensor measures, and whether it signals new readings on its interrupt
+    /// line (which the PIC forwards as its ``ccs`` source).
+    pub fn set_drive_mode(&mut self, wake: &mut impl WakeLine, mode: DriveMode, interrupt: bool) -> Result<(), Error>
+    {
+        let bits = mode.to_bits() | if interrupt { MEAS_MODE_INT_DATARDY } else { 0 };
+        self.awake(wake, |s| {
+            s.i2c.write(ADDR, &[MEAS_MODE, bits])?;
+            let status = Status(s.read_register::<1>(STATUS)?[0]);
+            s.check(status)?;
+            Ok(())
+        })
+    }
+
+    /// Read the latest measurement, or ``WouldBlock`` if there was none since the last read.
+    pub fn read(&mut self, wake: &mut impl WakeLine) -> nb::Result<Reading, Error>
+    {
+        let data: [u8; 8] = self.awake(wake, |s| {
+            let data = s.read_register(ALG_RESULT_DATA)?;
+            s.check(Status(data[4]))?;
+            Ok(data)
+        })?;
+
+        if !Status(data[4]).data_ready() {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(Reading {
+            eco2: u16::from_be_bytes([data[0], data[1]]),
+            tvoc: u16::from_be_bytes([data[2], data[3]]),
+            current: data[6] >> 2,
+            raw: u16::from_be_bytes([data[6] & 0x03, data[7]]),
+        })
+    }
+
+    /// Tell the sensor the ambient temperature (in centi-degrees Celsius) and relative humidity (in
+    /// per-mille) to compensate its readings for; see ``env_data``.
+    pub fn set_environment(&mut self, wake: &mut impl WakeLine, temperature: i32, humidity: u16) -> Result<(), Error>
+    {
+        let [h1, h0, t1, t0] = env_data(temperature, humidity);
+        self.awake(wake, |s| Ok(s.i2c.write(ADDR, &[ENV_DATA, h1, h0, t1, t0])?))
+    }
+
+    /// Reset the sensor into its boot loader; ``start_app`` needs to be run again afterwards.
+    pub fn reset(&mut self, wake: &mut impl WakeLine, delay: &mut impl DelayMs<u8>) -> Result<(), Error>
+    {
+        self.awake(wake, |s| Ok(s.i2c.write(ADDR, &SW_RESET)?))?;
+        delay.delay_ms(BOOT_MS);
+        Ok(())
+    }
+
+    pub fn free(self) -> I2cProxy<'a>
+    {
+        self.i2c
+    }
+}
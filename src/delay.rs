@@ -0,0 +1,183 @@
+//! Delay providers that can be shared between the main loop and interrupt handlers.
+//!
+//! The board has only one SysTick, but several users that need to wait (the application, the PIC
+//! abstraction and whatever is running in interrupts). A ``RefCell`` around the SysTick delay
+//! would panic as soon as an interrupt handler tries to delay while the main loop is inside a
+//! delay, so the ``SharedDelay`` here guards it with a critical section instead.
+//!
+//! Long delays are split into chunks, each of which runs in its own critical section; that way,
+//! interrupts are only held off for the duration of one chunk, and the delay can accept ``u32``
+//! durations even though a single SysTick wrap only lasts for well under a second.
+//!
+//! Where the SysTick is not available, the ``CycleDelay`` provides the same interface by counting
+//! CPU cycles.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{self, Mutex};
+use efm32gg_hal::systick::SystickDelay;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+/// Longest delay (in microseconds) that is performed in a single critical section.
+///
+/// This needs to stay below what the SysTick can count in one wrap (2**24 ticks, which is about
+/// 880ms at the default 19MHz), and is kept short so that a pending interrupt is not held off too
+/// long.
+const CHUNK_US: u32 = 10_000;
+
+/// A handle to a SysTick delay that may be used concurrently from several execution contexts.
+///
+/// Any number of these can be created around the same mutex; an interrupt that fires while
+/// another handle is waiting will be served at the end of the current chunk (at most 10ms), and
+/// the interrupted delay is extended by the time spent in the interrupt.
+#[derive(Clone, Copy)]
+pub struct SharedDelay {
+    mutex: &'static Mutex<RefCell<SystickDelay>>,
+}
+
+impl SharedDelay {
+    pub fn new(delay: &'static Mutex<RefCell<SystickDelay>>) -> Self
+    {
+        Self { mutex: delay }
+    }
+
+    fn delay_chunk(&self, us: u32)
+    {
+        interrupt::free(|cs| self.mutex.borrow(cs).borrow_mut().delay_us(us))
+    }
+}
+
+impl DelayUs<u32> for SharedDelay
+{
+    fn delay_us(&mut self, mut us: u32)
+    {
+        while us > CHUNK_US {
+            self.delay_chunk(CHUNK_US);
+            us -= CHUNK_US;
+        }
+        if us > 0 {
+            self.delay_chunk(us);
+        }
+    }
+}
+
+impl DelayUs<u16> for SharedDelay
+{
+    fn delay_us(&mut self, us: u16)
+    {
+        self.delay_us(u32::from(us))
+    }
+}
+
+impl DelayUs<u8> for SharedDelay
+{
+    fn delay_us(&mut self, us: u8)
+    {
+        self.delay_us(u32::from(us))
+    }
+}
+
+impl DelayMs<u32> for SharedDelay
+{
+    fn delay_ms(&mut self, ms: u32)
+    {
+        // Not multiplying ms up front to avoid overflowing for delays beyond 71 minutes
+        for _ in 0..ms {
+            self.delay_us(1000u32);
+        }
+    }
+}
+
+impl DelayMs<u16> for SharedDelay
+{
+    fn delay_ms(&mut self, ms: u16)
+    {
+        self.delay_ms(u32::from(ms))
+    }
+}
+
+impl DelayMs<u8> for SharedDelay
+{
+    fn delay_ms(&mut self, ms: u8)
+    {
+        self.delay_ms(u32::from(ms))
+    }
+}
+
+/// Core clock frequency the ``CycleDelay`` calculates with
+///
+/// FIXME: Like the HAL's HFCoreClk, this assumes that nothing has been changed about the clocks
+/// since reset.
+const HFCORECLK_MHZ: u32 = 19;
+
+/// A delay that busy-waits by counting CPU cycles
+///
+/// This does not use any peripheral and has no state, so any number of these can be created and
+/// used from any execution context without coordination; that makes it the delay of choice where
+/// the SysTick is not available (eg. when a framework has taken the core peripherals). Being
+/// interrupted only makes it wait longer. The delays are approximate, and rather too long than
+/// too short.
+#[derive(Clone, Copy, Default)]
+pub struct CycleDelay;
+
+impl CycleDelay {
+    pub fn new() -> Self
+    {
+        CycleDelay
+    }
+}
+
+impl DelayUs<u32> for CycleDelay
+{
+    fn delay_us(&mut self, mut us: u32)
+    {
+        // Chunked to keep the cycle count from overflowing
+        while us > CHUNK_US {
+            cortex_m::asm::delay(CHUNK_US * HFCORECLK_MHZ);
+            us -= CHUNK_US;
+        }
+        cortex_m::asm::delay(us * HFCORECLK_MHZ);
+    }
+}
+
+impl DelayUs<u16> for CycleDelay
+{
+    fn delay_us(&mut self, us: u16)
+    {
+        self.delay_us(u32::from(us))
+    }
+}
+
+impl DelayUs<u8> for CycleDelay
+{
+    fn delay_us(&mut self, us: u8)
+    {
+        self.delay_us(u32::from(us))
+    }
+}
+
+impl DelayMs<u32> for CycleDelay
+{
+    fn delay_ms(&mut self, ms: u32)
+    {
+        for _ in 0..ms {
+            self.delay_us(1000u32);
+        }
+    }
+}
+
+impl DelayMs<u16> for CycleDelay
+{
+    fn delay_ms(&mut self, ms: u16)
+    {
+        self.delay_ms(u32::from(ms))
+    }
+}
+
+impl DelayMs<u8> for CycleDelay
+{
+    fn delay_ms(&mut self, ms: u8)
+    {
+        self.delay_ms(u32::from(ms))
+    }
+}
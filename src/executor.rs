@@ -0,0 +1,53 @@
+//! A minimal executor for running a single future to completion
+//!
+//! This is not meant to replace a full async executor, but suffices to drive the board's futures
+//! from the main function: Whenever the future is pending, the core sleeps (WFE) until an
+//! interrupt or a wake-up happens, rather than spinning. ``block_on_sleeping`` does the same,
+//! but can sleep deeper (see the ``sleep`` module).
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use crate::sleep;
+
+// The waker carries no data: Waking just sets the event flag so that the WFE in block_on returns
+// (or does not even start sleeping, if the wake happened between polling and sleeping).
+static VTABLE: RawWakerVTable = RawWakerVTable::new(
+    |_| RawWaker::new(core::ptr::null(), &VTABLE),
+    |_| cortex_m::asm::sev(),
+    |_| cortex_m::asm::sev(),
+    |_| (),
+    );
+
+/// Run a future to completion, sleeping whenever it is pending.
+pub fn block_on<F: Future>(future: F) -> F::Output
+{
+    run(future, cortex_m::asm::wfe)
+}
+
+/// Run a future to completion, sleeping in the given energy mode whenever it is pending.
+///
+/// The wake sources of the future (eg. the GPIO lines or the RTCC) need to be available in the
+/// given mode, or this sleeps until some other interrupt occurs.
+pub fn block_on_sleeping<F: Future>(mode: sleep::Mode, future: F) -> F::Output
+{
+    run(future, || sleep::wait_for_event(mode))
+}
+
+fn run<F: Future>(mut future: F, mut idle: impl FnMut()) -> F::Output
+{
+    // unsafe: The vtable functions uphold the RawWaker contract trivially, as there is no data.
+    let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+
+    // unsafe: The future is shadowed and thus never moved again after being pinned here.
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+
+    loop {
+        if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+            return result;
+        }
+        idle();
+    }
+}
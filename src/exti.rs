@@ -0,0 +1,159 @@
+//! GPIO external interrupts for the board's input lines
+//!
+//! This backs the asynchronous waiting functions of the buttons (PD14, PD15) and the PIC's
+//! interrupt line (PD10). The lines are armed when a future starts waiting on them, and disarmed
+//! by the interrupt handler when they fire, so an idle line does not cause any interrupts.
+//!
+//! For any of that to work, the application needs to enable the GPIO_EVEN and GPIO_ODD
+//! interrupts in the NVIC and call ``on_interrupt`` from both handlers:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn GPIO_EVEN() { thunderboard_sltb001a::exti::on_interrupt(); }
+//! #[interrupt]
+//! fn GPIO_ODD() { thunderboard_sltb001a::exti::on_interrupt(); }
+//! ```
+//!
+//! The external interrupt lines are numbered like the pins, so line 10 is PD10 and lines 14 and 15
+//! are the buttons; the other lines are left to the application (``on_interrupt`` only touches
+//! the flags of the lines managed here).
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::Waker;
+
+use cortex_m::interrupt::{self, Mutex};
+use efr32xg1 as registers;
+
+pub(crate) const LINE_PIC: u8 = 10;
+pub(crate) const LINE_BUTTON0: u8 = 14;
+pub(crate) const LINE_BUTTON1: u8 = 15;
+
+const MANAGED: u32 = (1 << LINE_PIC) | (1 << LINE_BUTTON0) | (1 << LINE_BUTTON1);
+
+/// Lines that fired since they were last armed
+static FIRED: AtomicU32 = AtomicU32::new(0);
+
+/// Wakers for the managed lines, in the order PIC, button 0, button 1
+static WAKERS: Mutex<RefCell<[Option<Waker>; 3]>> = Mutex::new(RefCell::new([None, None, None]));
+
+fn waker_index(line: u8) -> usize
+{
+    match line {
+        LINE_PIC => 0,
+        LINE_BUTTON0 => 1,
+        LINE_BUTTON1 => 2,
+        _ => unreachable!(),
+    }
+}
+
+fn gpio() -> &'static registers::gpio::RegisterBlock {
+    // unsafe: Only the EXTI registers are accessed through this, and only for the managed lines.
+    unsafe { &*registers::GPIO::ptr() }
+}
+
+/// Route the managed lines to their port D pins, to trigger on falling edges. This does not arm
+/// them yet.
+pub(crate) fn configure()
+{
+    let gpio = gpio();
+    interrupt::free(|_| {
+        gpio.extipselh.modify(|_, w| w
+            .extipsel10().portd()
+            .extipsel14().portd()
+            .extipsel15().portd()
+            );
+        gpio.extipinselh.modify(|_, w| w
+            .extipinsel10().pin10()
+            .extipinsel14().pin14()
+            .extipinsel15().pin15()
+            );
+        gpio.extifall.modify(|r, w| unsafe { w.bits(r.bits() | MANAGED) });
+    });
+}
+
+/// Arm a line, clearing any earlier firing of it.
+pub(crate) fn arm(line: u8)
+{
+    let gpio = gpio();
+    FIRED.fetch_and(!(1 << line), Ordering::SeqCst);
+    interrupt::free(|_| {
+        gpio.ifc.write(|w| unsafe { w.bits(1 << line) });
+        gpio.ien.modify(|r, w| unsafe { w.bits(r.bits() | (1 << line)) });
+    });
+}
+
+/// Disarm a line, eg. when the future waiting for it is dropped.
+pub(crate) fn disarm(line: u8)
+{
+    let gpio = gpio();
+    interrupt::free(|_| {
+        gpio.ien.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << line)) });
+    });
+}
+
+/// Check whether a line fired since it was armed, and register to be woken when it does
+/// otherwise.
+pub(crate) fn poll_fired(line: u8, waker: &Waker) -> bool
+{
+    interrupt::free(|cs| {
+        if FIRED.load(Ordering::SeqCst) & (1 << line) != 0 {
+            return true;
+        }
+        WAKERS.borrow(cs).borrow_mut()[waker_index(line)] = Some(waker.clone());
+        false
+    })
+}
+
+/// Check whether a (port D) line is currently held low.
+pub(crate) fn is_low(line: u8) -> bool
+{
+    gpio().pd_din.read().bits() & (1 << line) == 0
+}
+
+/// Stop reacting to a line for a moment, returning whether it was armed.
+///
+/// This is used by the PIC abstraction, as driving the shared INT/WAKE line low to wake the PIC
+/// also produces a falling edge that should not count as an interrupt.
+pub(crate) fn suspend(line: u8) -> bool
+{
+    let gpio = gpio();
+    interrupt::free(|_| {
+        let was_armed = gpio.ien.read().bits() & (1 << line) != 0;
+        gpio.ien.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << line)) });
+        was_armed
+    })
+}
+
+/// Undo a ``suspend``, ignoring any edges that happened in the meantime.
+pub(crate) fn resume(line: u8, was_armed: bool)
+{
+    let gpio = gpio();
+    interrupt::free(|_| {
+        gpio.ifc.write(|w| unsafe { w.bits(1 << line) });
+        if was_armed {
+            gpio.ien.modify(|r, w| unsafe { w.bits(r.bits() | (1 << line)) });
+        }
+    });
+}
+
+/// Handle the GPIO_EVEN and GPIO_ODD interrupts for the board's lines.
+pub fn on_interrupt()
+{
+    let gpio = gpio();
+    interrupt::free(|cs| {
+        let pending = gpio.if_.read().bits() & gpio.ien.read().bits() & MANAGED;
+        gpio.ifc.write(|w| unsafe { w.bits(pending) });
+        gpio.ien.modify(|r, w| unsafe { w.bits(r.bits() & !pending) });
+        FIRED.fetch_or(pending, Ordering::SeqCst);
+
+        let mut wakers = WAKERS.borrow(cs).borrow_mut();
+        for line in [LINE_PIC, LINE_BUTTON0, LINE_BUTTON1].iter() {
+            if pending & (1 << line) != 0 {
+                if let Some(w) = wakers[waker_index(*line)].take() {
+                    w.wake();
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,157 @@
+//! EM4 hibernation
+//!
+//! In EM4 hibernate, almost everything is powered down: RAM content and peripheral state are
+//! lost, and waking up looks like a reset to the program. What survives are the 32 retention
+//! registers of the RTCC (and the RTCC itself, which keeps running from the retained LFXO).
+//!
+//! ``hibernate`` switches off the PIC's domains, stores a small application ``State`` in the
+//! retention registers and enters EM4 until button 0 (PD14) is pressed or the RTCC alarm goes off.
+//! On the next start, ``Board::new`` finds out why it is booting, and reports that in its ``boot``
+//! field:
+//!
+//! ```ignore
+//! let mut board = Board::new();
+//! let mut state = match board.boot {
+//!     hibernate::Boot::Em4Wake(state) => state,
+//!     hibernate::Boot::Cold => [0; hibernate::STATE_WORDS],
+//! };
+//! state[0] += 1;
+//! hibernate::hibernate(&mut board.pic, &mut board.rtc, hibernate::Wake { button0: true, after: Some(Seconds(60)) }, &state);
+//! ```
+//!
+//! Only button 0 can wake the device: PD15 (button 1) is not among the EFR32's EM4 wake-up pins.
+//! Note that EM4 is not entered while a debugger is attached.
+
+use efr32xg1 as registers;
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::pic::{InterruptSet, PIC};
+use crate::rtc::{Rtc, RETAINED_WORDS};
+use crate::sleep;
+use crate::time::Seconds;
+
+/// Number of 32-bit words that can be kept through hibernation
+pub const STATE_WORDS: usize = RETAINED_WORDS - 1;
+
+/// Application state kept through hibernation
+pub type State = [u32; STATE_WORDS];
+
+/// Marks valid content of the other retention registers (the first one holds this)
+const MAGIC: u32 = 0x454d_3421;
+
+/// Index of the EM4 wake-up pin PD14 in the GPIO EM4WUEN and EXTILEVEL registers
+const EM4WU_PD14: u32 = 1 << (16 + 4);
+
+/// Why the program is starting
+#[derive(Clone, Copy, Debug)]
+pub enum Boot {
+    /// Power-up, reset or any other start that does not continue a hibernation
+    Cold,
+    /// Wake-up from EM4, with the state that was stored when entering it
+    Em4Wake(State),
+}
+
+/// Events that end hibernation
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Wake {
+    /// Wake when button 0 is pressed
+    pub button0: bool,
+    /// Wake after this time, using the RTCC alarm (which replaces any alarm set before)
+    pub after: Option<Seconds>,
+}
+
+/// Find out whether the device woke from EM4, and recover the stored state.
+///
+/// The retained state is invalidated, so that a later reset does not mistake it for current.
+pub(crate) fn boot(rtc: &mut Rtc) -> Boot
+{
+    // unsafe: The reset management and EMU commands are not used anywhere else
+    let rmu = unsafe { &*registers::RMU::ptr() };
+    let emu = unsafe { &*registers::EMU::ptr() };
+
+    let cause = rmu.rstcause.read();
+    // The other cause bits are only meaningful if there was no power-on reset
+    let em4_wake = cause.porst().bit_is_clear() && cause.em4rst().bit_is_set();
+    rmu.cmd.write(|w| w.rcclr().set_bit());
+    // Release the pins that were held through EM4
+    emu.cmd.write(|w| w.em4unlatch().set_bit());
+
+    let valid = rtc.retained(0) == MAGIC;
+    rtc.set_retained(0, 0);
+
+    if em4_wake && valid {
+        let mut state = [0; STATE_WORDS];
+        for (i, word) in state.iter_mut().enumerate() {
+            *word = rtc.retained(i + 1);
+        }
+        Boot::Em4Wake(state)
+    } else {
+        Boot::Cold
+    }
+}
+
+/// Power down the PIC domains, store the state and enter EM4 hibernation.
+///
+/// This does not return; the device starts over when one of the ``wake`` events happens. If no
+/// wake event is configured, only a reset ends the hibernation.
+///
+/// The LEDs should be switched off before, as the MCU's pins keep their state during EM4.
+pub fn hibernate<D, I, E>(pic: &mut PIC<D, I>, rtc: &mut Rtc, wake: Wake, state: &State) -> !
+    where D: DelayUs<u16>,
+          I: Write<Error = E> + Read<Error = E>,
+          E: core::fmt::Debug,
+{
+    // Hibernating with a domain left on beats not hibernating at all
+    let _ = pic.set_int(InterruptSet { ccs: false, imu: false, uv: false });
+    let _ = pic.set_leds(false, false, false, false);
+    let _ = pic.set_imu(false);
+    let _ = pic.set_env_sensor(false);
+    let _ = pic.set_mic(false);
+    let _ = pic.set_ccs(false, false);
+
+    for (i, word) in state.iter().enumerate() {
+        rtc.set_retained(i + 1, *word);
+    }
+    rtc.set_retained(0, MAGIC);
+
+    match wake.after {
+        Some(after) => rtc.set_alarm_in(after),
+        None => rtc.clear_alarm(),
+    }
+    rtc.prepare_em4();
+
+    // unsafe: Only the EM4 wake-up configuration of PD14 is touched; the EMU is not used
+    // elsewhere.
+    let gpio = unsafe { &*registers::GPIO::ptr() };
+    let emu = unsafe { &*registers::EMU::ptr() };
+
+    cortex_m::interrupt::disable();
+
+    if wake.button0 {
+        // The button pulls the line low
+        gpio.extilevel.modify(|r, w| unsafe { w.bits(r.bits() & !EM4WU_PD14) });
+        gpio.ifc.write(|w| unsafe { w.bits(EM4WU_PD14) });
+        gpio.em4wuen.modify(|r, w| unsafe { w.bits(r.bits() | EM4WU_PD14) });
+    } else {
+        gpio.em4wuen.modify(|r, w| unsafe { w.bits(r.bits() & !EM4WU_PD14) });
+    }
+
+    emu.em4ctrl.modify(|_, w| w
+        // hibernate rather than shutoff, so that the RTCC and its retention registers stay
+        .em4state().set_bit()
+        .retainlfxo().set_bit()
+        // keep the button's input configuration until the wake-up
+        .em4ioretmode().em4exit()
+        );
+
+    sleep::set_sleepdeep(true);
+    // The EM4 entry sequence from the reference manual
+    for value in [2, 3, 2, 3, 2, 3, 2, 3, 2].iter() {
+        emu.em4ctrl.modify(|_, w| unsafe { w.em4entry().bits(*value) });
+    }
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -0,0 +1,154 @@
+//! Quantities derived from temperature and relative humidity
+//!
+//! All functions take temperatures in centi-degrees Celsius and relative humidity in per-mille
+//! (as produced by the ``si7021`` driver), and compute in fixed point, so they do not pull the
+//! soft-float routines into the binary. They are accurate to a few hundredths of a degree or a few
+//! per-mille, which is well within the sensor's accuracy.
+//!
+//! The saturation vapour pressure is approximated with the Magnus formula using Sonntag's
+//! constants (b = 17.62, c = 243.12°C), which holds from -45°C to 60°C.
+
+/// Magnus constant b, in Q16
+const MAGNUS_B_Q16: i64 = 1_154_744;
+/// Magnus constant c, in centi-degrees
+const MAGNUS_C: i64 = 24_312;
+
+/// ln(2) and 1/ln(2), in Q16
+const LN_2_Q16: i64 = 45_426;
+const LOG2_E_Q16: i64 = 94_548;
+
+/// 2^(2^-k) for k = 1..16, in Q16
+const POW2_FRACTIONS: [u64; 16] = [
+    92682, 77936, 71468, 68438, 66971, 66250, 65892, 65714,
+    65625, 65580, 65558, 65547, 65542, 65539, 65537, 65537,
+];
+
+/// Base 2 logarithm of a positive integer, in Q16
+fn log2_q16(x: u32) -> i64
+{
+    let int = 31 - x.leading_zeros();
+    // Normalized to [1, 2) in Q30; each squaring yields one bit of the fraction
+    let mut m = (u64::from(x) << 30) >> int;
+    let mut result = i64::from(int) << 16;
+    for bit in (0..16).rev() {
+        m = (m * m) >> 30;
+        if m >= 2 << 30 {
+            m >>= 1;
+            result |= 1 << bit;
+        }
+    }
+    result
+}
+
+/// Natural logarithm of a positive integer, in Q16
+fn ln_q16(x: u32) -> i64
+{
+    (log2_q16(x) * LN_2_Q16) >> 16
+}
+
+/// e to the power of a Q16 number, in Q16
+///
+/// Only meant for the small arguments that occur here; large ones overflow.
+fn exp_q16(x: i64) -> u64
+{
+    let y = (x * LOG2_E_Q16) >> 16;
+    let (int, frac) = (y >> 16, y & 0xffff);
+    let mut result: u64 = 1 << 16;
+    for (k, factor) in POW2_FRACTIONS.iter().enumerate() {
+        if frac & (0x8000 >> k) != 0 {
+            result = (result * factor) >> 16;
+        }
+    }
+    if int >= 0 { result << int } else { result >> -int }
+}
+
+fn isqrt(mut x: u64) -> u64
+{
+    let mut root = 0;
+    let mut bit = 1 << 62;
+    while bit > x {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if x >= root + bit {
+            x -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+/// The exponent b·T/(c + T) of the Magnus formula, in Q16
+fn magnus_q16(temperature: i32) -> i64
+{
+    let t = i64::from(temperature);
+    MAGNUS_B_Q16 * t / (MAGNUS_C + t)
+}
+
+/// Dew point in centi-degrees Celsius
+///
+/// Humidity readings of 0 are treated as 0.1%, where the dew point is far below anything the
+/// formula is good for anyway.
+pub fn dew_point(temperature: i32, humidity: u16) -> i32
+{
+    let gamma = ln_q16(u32::from(humidity.max(1))) - ln_q16(1000) + magnus_q16(temperature);
+    (MAGNUS_C * gamma / (MAGNUS_B_Q16 - gamma)) as i32
+}
+
+/// Absolute humidity in milligrams of water per cubic metre
+pub fn absolute_humidity(temperature: i32, humidity: u16) -> u32
+{
+    // Saturation vapour pressure in Pa (611.2Pa at 0°C), and the actual vapour pressure, in Q16
+    let saturation = 6112 * exp_q16(magnus_q16(temperature)) / 10;
+    let vapour = saturation * u64::from(humidity) / 1000;
+    // Ideal gas law with the specific gas constant of water vapour (461.5 J/(kg K)), with the
+    // temperature in centi-kelvin
+    let kelvin = (i64::from(temperature) + 27_315).max(1) as u64;
+    ((216_700 * vapour / kelvin) >> 16) as u32
+}
+
+/// Heat index ("felt air temperature") in centi-degrees Celsius
+///
+/// This follows the US National Weather Service's algorithm: Steadman's simple formula for mild
+/// conditions, and the Rothfusz regression with its adjustments for very dry or humid air when
+/// that yields 80°F or more.
+pub fn heat_index(temperature: i32, humidity: u16) -> i32
+{
+    // The formulas work in degrees Fahrenheit and percent; t is in centi-°F, r in per-mille
+    let t = i64::from(temperature) * 9 / 5 + 3200;
+    let r = i64::from(humidity);
+
+    let simple = (t + 6100 + (t - 6800) * 12 / 10 + r * 94 / 100) / 2;
+
+    let index = if (simple + t) / 2 < 8000 {
+        simple
+    } else {
+        // Coefficients scaled by 10^8, each term scaled to a common denominator of 10^14 (for
+        // the centi-°F and per-mille inputs)
+        let sum = -4_237_900_000 * 1_000_000
+            + 204_901_523 * t * 10_000
+            + 1_014_333_127 * r * 100_000
+            - 22_475_541 * t * r * 1_000
+            - 683_783 * t * t * 100
+            - 5_481_717 * r * r * 10_000
+            + 122_874 * t * t * r * 10
+            + 85_282 * t * r * r * 100
+            - 199 * t * t * r * r;
+        let mut index = sum / 1_000_000_000_000;
+
+        if r < 130 && (8000..=11200).contains(&t) {
+            // - (13 - RH) / 4 * sqrt((17 - |T - 95|) / 17)
+            let root = isqrt(((1700 - (t - 9500).abs()) as u64) * (1 << 32) / 1700) as i64;
+            index -= ((130 - r) * 5 * root) >> 17;
+        } else if r > 850 && (8000..=8700).contains(&t) {
+            // + (RH - 85) / 10 * (87 - T) / 5
+            index += (r - 850) * (8700 - t) / 500;
+        }
+        index
+    };
+
+    ((index - 3200) * 5 / 9) as i32
+}
@@ -0,0 +1,429 @@
+//! Sharing the I2C0 bus between the PIC and the sensors
+//!
+//! The board's PIC and its environmental sensors (Si7021, Si1133, BMP280, CCS811) all sit on I2C0.
+//! The ``I2cBus`` owns the configured peripheral, and hands out any number of ``I2cProxy``s that
+//! implement the blocking embedded-hal I2C traits, so that each driver can hold its own proxy.
+//!
+//! A transaction claims the bus for its duration, but runs with interrupts enabled; only claiming
+//! and releasing happen in a critical section. The proxies are usable from the main loop and from
+//! interrupt handlers alike. An interrupt handler that finds the bus claimed by the code it
+//! interrupted can't wait for it, and gets ``Error::Busy`` instead.
+//!
+//! A device that loses power in the middle of a transfer (which happens easily when switching
+//! PIC power domains) can leave the bus hanging with SDA held low, and the HAL's I2C
+//! implementation would wait for it forever. The proxies therefore run their transfers with a
+//! timeout, and follow a ``RetryPolicy`` that can recover the bus (by clocking SCL until SDA is
+//! released and then issuing a STOP) and retry failed transactions.
+//!
+//! For diagnosing such failures, the transactions can be recorded as described in the
+//! ``i2c_trace`` module.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{self, Mutex};
+use efr32xg1 as registers;
+use efm32gg_hal::cmu::I2C0Clk;
+use efm32gg_hal::gpio::Disabled;
+use efm32gg_hal::gpio::pins::{PC10, PC11};
+use efm32gg_hal::i2c::{self, ConfiguredI2C0, I2CExt};
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+/// Rough number of state register polls per microsecond while waiting for the peripheral
+///
+/// FIXME: This assumes the default 19MHz clock; the timeouts are approximate anyway.
+const POLLS_PER_US: u32 = 2;
+
+/// Configure I2C0 on the pins the board's devices are connected to (SCL on PC11, SDA on PC10).
+pub fn configure(register: registers::I2C0, clk: I2C0Clk, pc11: PC11<Disabled>, pc10: PC10<Disabled>) -> ConfiguredI2C0
+{
+    register.with_clock(clk).with_scl(registers::i2c0::routeloc0::SCLLOCW::LOC15, pc11).unwrap().with_sda(registers::i2c0::routeloc0::SDALOCW::LOC15, pc10).unwrap()
+}
+
+/// Errors of transactions through an ``I2cProxy``
+#[derive(Debug)]
+pub enum Error {
+    /// An error as the HAL would report it
+    Bus(i2c::Error),
+    /// The peripheral did not reach the expected state in time, eg. because the bus hangs
+    Timeout,
+    /// The bus is in use by a transaction that was interrupted by the caller
+    Busy,
+}
+
+/// Kind of a transaction, as used by the transaction trace
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Write,
+    Read,
+    /// A write followed by a read
+    WriteRead,
+}
+
+/// How the bus reacts to failed transactions
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// How often a failed transaction is repeated before the error is reported
+    pub retries: u8,
+    /// Time a single attempt may wait for the peripheral before it is aborted (approximate)
+    pub timeout_us: u32,
+    /// Whether a missing acknowledgement is retried (eg. for devices that are busy at times), or
+    /// reported right away (eg. when scanning the bus)
+    pub retry_on_nack: bool,
+    /// Whether to run a bus recovery after a timeout or when the bus was found busy
+    pub recover: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self
+    {
+        RetryPolicy {
+            retries: 2,
+            timeout_us: 10_000,
+            retry_on_nack: false,
+            recover: true,
+        }
+    }
+}
+
+struct Inner {
+    i2c: ConfiguredI2C0,
+    policy: RetryPolicy,
+    /// Whether a transaction (or recovery) is in progress
+    busy: bool,
+}
+
+/// Owner of the I2C0 peripheral that hands out proxies to it
+pub struct I2cBus {
+    inner: Mutex<RefCell<Inner>>,
+}
+
+impl I2cBus {
+    pub fn new(i2c: ConfiguredI2C0) -> Self
+    {
+        I2cBus { inner: Mutex::new(RefCell::new(Inner { i2c, policy: RetryPolicy::default(), busy: false })) }
+    }
+
+    /// Create a new handle to the bus.
+    pub fn acquire(&self) -> I2cProxy<'_>
+    {
+        I2cProxy { bus: self }
+    }
+
+    /// Change how all users of the bus react to failed transactions.
+    pub fn set_policy(&self, policy: RetryPolicy)
+    {
+        interrupt::free(|cs| self.inner.borrow(cs).borrow_mut().policy = policy)
+    }
+
+    /// The currently active retry policy
+    pub fn policy(&self) -> RetryPolicy
+    {
+        interrupt::free(|cs| self.inner.borrow(cs).borrow().policy)
+    }
+
+    /// Run a sequence of operations on the bus without any other user interleaving.
+    ///
+    /// This gives direct access to the HAL peripheral, so no timeouts or retries apply, and it
+    /// runs in a critical section. Like the proxies' transactions, it fails with ``Error::Busy``
+    /// when called from an interrupt handler that interrupted a transaction.
+    pub fn with<R>(&self, f: impl FnOnce(&mut ConfiguredI2C0) -> R) -> Result<R, Error>
+    {
+        interrupt::free(|cs| {
+            let mut inner = self.inner.borrow(cs).borrow_mut();
+            if inner.busy {
+                return Err(Error::Busy);
+            }
+            Ok(f(&mut inner.i2c))
+        })
+    }
+
+    /// Mark the bus as in use until the claim is dropped, returning the retry policy.
+    fn claim(&self) -> Result<(Claim<'_>, RetryPolicy), Error>
+    {
+        interrupt::free(|cs| {
+            let mut inner = self.inner.borrow(cs).borrow_mut();
+            if inner.busy {
+                return Err(Error::Busy);
+            }
+            inner.busy = true;
+            Ok((Claim { bus: self }, inner.policy))
+        })
+    }
+
+    /// Free a hanging bus.
+    ///
+    /// This happens automatically as the retry policy says, but can be triggered manually, eg.
+    /// after re-powering a sensor domain.
+    pub fn recover(&self) -> Result<(), Error>
+    {
+        let _claim = self.claim()?;
+        recover();
+        Ok(())
+    }
+
+    /// Run a transaction repeatedly as the retry policy says.
+    fn transaction(&self, addr: u8, direction: Direction, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error>
+    {
+        let (claim, policy) = match self.claim() {
+            Ok(claimed) => claimed,
+            Err(e) => {
+                let result = Err(e);
+                #[cfg(feature = "i2c-trace")]
+                crate::i2c_trace::record(addr, direction, bytes, buffer, 0, &result);
+                return result;
+            }
+        };
+        let budget = policy.timeout_us.saturating_mul(POLLS_PER_US);
+
+        let mut retries = 0;
+        let result = loop {
+            let result = match direction {
+                Direction::Write => write(budget, addr, bytes),
+                Direction::Read => read(budget, addr, buffer),
+                Direction::WriteRead => write(budget, addr, bytes).and_then(|()| read(budget, addr, buffer)),
+            };
+            let err = match result {
+                Ok(()) => break Ok(()),
+                Err(e) => e,
+            };
+
+            let (retry, recover_first) = match err {
+                Error::Timeout | Error::Bus(i2c::Error::NotReady) => (true, policy.recover),
+                Error::Bus(i2c::Error::ArbitrationLost) => (true, false),
+                Error::Bus(i2c::Error::AddressNack) | Error::Bus(i2c::Error::DataNack) => (policy.retry_on_nack, false),
+                Error::Busy => (false, false),
+            };
+
+            if !retry || retries >= policy.retries {
+                break Err(err);
+            }
+            if recover_first {
+                recover();
+            }
+            retries += 1;
+        };
+        drop(claim);
+
+        #[cfg(feature = "i2c-trace")]
+        crate::i2c_trace::record(addr, direction, bytes, buffer, retries, &result);
+
+        result
+    }
+}
+
+/// Proof of having marked the bus as busy, which releases it when dropped
+struct Claim<'a> {
+    bus: &'a I2cBus,
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self)
+    {
+        interrupt::free(|cs| self.bus.inner.borrow(cs).borrow_mut().busy = false)
+    }
+}
+
+fn regs() -> &'static registers::i2c0::RegisterBlock
+{
+    // unsafe: Only used while the bus is claimed (or borrowed in ``with``), and the bus owns the
+    // ConfiguredI2C0
+    unsafe { &*registers::I2C0::ptr() }
+}
+
+/// Poll the state register until ``accept`` returns a result, or the budget is used up.
+fn wait_state<T>(budget: u32, mut accept: impl FnMut(u32) -> Option<Result<T, Error>>) -> Result<T, Error>
+{
+    let i2c = regs();
+    for _ in 0..budget {
+        if let Some(result) = accept(i2c.state.read().bits()) {
+            return result;
+        }
+    }
+    // Otherwise, the next transaction would find the peripheral busy
+    i2c.cmd.write(|w| w.abort().set_bit());
+    Err(Error::Timeout)
+}
+
+/// Set stop condition on bus and wait for bus to return to idle (or busy) state.
+fn stop_and_finish(budget: u32) -> Result<(), Error>
+{
+    regs().cmd.write(|w| w.stop().set_bit());
+    wait_state(budget, |state| if state <= 1 { Some(Ok(())) } else { None })
+}
+
+/// Release the bus after an attempt failed, and report the error.
+fn fail(budget: u32, e: Error) -> Result<(), Error>
+{
+    match e {
+        // After a NACK, the peripheral is still master and needs to send a STOP
+        Error::Bus(i2c::Error::AddressNack) | Error::Bus(i2c::Error::DataNack) => stop_and_finish(budget)?,
+        _ => (),
+    }
+    Err(e)
+}
+
+/// Send a start condition and the address byte.
+///
+/// The state values used here and below follow the HAL's implementation of the state diagrams
+/// in the EFR32xG1 Reference Manual.
+fn start(budget: u32, address_byte: u8) -> Result<(), Error>
+{
+    let i2c = regs();
+    if i2c.state.read().bits() > 1 {
+        return Err(Error::Bus(i2c::Error::NotReady));
+    }
+
+    i2c.cmd.write(|w| w.start().set_bit());
+    wait_state(budget, |state| match state {
+        0x53 | 0x57 => Some(Ok(())),
+        _ => None,
+    })?;
+    i2c.txdata.write(|w| unsafe { w.txdata().bits(address_byte) });
+    Ok(())
+}
+
+fn write(budget: u32, addr: u8, bytes: &[u8]) -> Result<(), Error>
+{
+    let i2c = regs();
+    start(budget, addr << 1)?;
+
+    wait_state(budget, |state| match state {
+        1 => Some(Err(Error::Bus(i2c::Error::ArbitrationLost))),
+        0x9f => Some(Err(Error::Bus(i2c::Error::AddressNack))),
+        0x97 => Some(Ok(())),
+        _ => None,
+    }).or_else(|e| fail(budget, e))?;
+
+    for datum in bytes.iter() {
+        i2c.txdata.write(|w| unsafe { w.txdata().bits(*datum) });
+        wait_state(budget, |state| match state {
+            1 => Some(Err(Error::Bus(i2c::Error::ArbitrationLost))),
+            0xdf => Some(Err(Error::Bus(i2c::Error::DataNack))),
+            0xd7 => Some(Ok(())),
+            _ => None,
+        }).or_else(|e| fail(budget, e))?;
+    }
+
+    stop_and_finish(budget)
+}
+
+fn read(budget: u32, addr: u8, buffer: &mut [u8]) -> Result<(), Error>
+{
+    let i2c = regs();
+    start(budget, (addr << 1) | 1)?;
+
+    let last = buffer.len();
+    for (i, datum) in buffer.iter_mut().enumerate() {
+        wait_state(budget, |state| match state {
+            1 => Some(Err(Error::Bus(i2c::Error::ArbitrationLost))),
+            0x9b => Some(Err(Error::Bus(i2c::Error::AddressNack))),
+            0xb3 => Some(Ok(())),
+            _ => None,
+        }).or_else(|e| fail(budget, e))?;
+
+        *datum = i2c.rxdata.read().bits() as u8;
+
+        if i + 1 == last {
+            i2c.cmd.write(|w| w.nack().set_bit());
+        } else {
+            i2c.cmd.write(|w| w.ack().set_bit());
+        }
+    }
+
+    stop_and_finish(budget)
+}
+
+/// Free the bus from a device that holds SDA low.
+///
+/// The pins are taken away from the I2C peripheral for the duration, and SCL is clocked (up to 9
+/// times, enough to finish any byte) until SDA is released. A STOP condition then brings all
+/// devices back into a defined state.
+fn recover()
+{
+    const SCL: u32 = 1 << 11;
+    const SDA: u32 = 1 << 10;
+
+    // unsafe: Only the port C data bits of the I2C pins are touched, and those belong to the bus.
+    let gpio = unsafe { &*registers::GPIO::ptr() };
+    let i2c = regs();
+    // About 5us at 19MHz, half a clock cycle at 100kHz
+    let half_period = || cortex_m::asm::delay(100);
+    let set = |bits: u32| gpio.pc_dout.modify(|r, w| unsafe { w.bits(r.bits() | bits) });
+    let clear = |bits: u32| gpio.pc_dout.modify(|r, w| unsafe { w.bits(r.bits() & !bits) });
+
+    // Both pins are open drain already, and released when their output is high
+    set(SCL | SDA);
+    i2c.routepen.modify(|_, w| w.sdapen().clear_bit().sclpen().clear_bit());
+
+    for _ in 0..9 {
+        if gpio.pc_din.read().bits() & SDA != 0 {
+            break;
+        }
+        clear(SCL);
+        half_period();
+        set(SCL);
+        half_period();
+    }
+
+    // STOP: SDA rising while SCL is high
+    clear(SCL);
+    half_period();
+    clear(SDA);
+    half_period();
+    set(SCL);
+    half_period();
+    set(SDA);
+    half_period();
+
+    i2c.routepen.modify(|_, w| w.sdapen().set_bit().sclpen().set_bit());
+    i2c.cmd.write(|w| w.abort().set_bit());
+}
+
+/// A handle to the shared I2C0 bus
+#[derive(Clone, Copy)]
+pub struct I2cProxy<'a> {
+    bus: &'a I2cBus,
+}
+
+impl I2cProxy<'_> {
+    /// Run a sequence of operations on the bus without any other user interleaving.
+    ///
+    /// This gives direct access to the HAL peripheral, so no timeouts or retries apply; see
+    /// ``I2cBus::with``.
+    pub fn with<R>(&mut self, f: impl FnOnce(&mut ConfiguredI2C0) -> R) -> Result<R, Error>
+    {
+        self.bus.with(f)
+    }
+}
+
+impl Write for I2cProxy<'_> {
+    type Error = Error;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error>
+    {
+        self.bus.transaction(addr, Direction::Write, bytes, &mut [])
+    }
+}
+
+impl Read for I2cProxy<'_> {
+    type Error = Error;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Error>
+    {
+        self.bus.transaction(addr, Direction::Read, &[], buffer)
+    }
+}
+
+impl WriteRead for I2cProxy<'_> {
+    type Error = Error;
+
+    /// Write and then read in one go.
+    ///
+    /// This does not use a repeated start yet, but sends a stop condition between the two, which
+    /// all the devices on the board accept. Other users of the bus can't interleave, though, and
+    /// a retry repeats both parts.
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error>
+    {
+        self.bus.transaction(addr, Direction::WriteRead, bytes, buffer)
+    }
+}
@@ -0,0 +1,194 @@
+//! Recording of the recent transactions on the shared I2C bus
+//!
+//! With the ``i2c-trace`` feature enabled, every transaction that goes through an
+//! ``i2c_bus::I2cProxy`` (and thus all the PIC's and the sensor drivers' accesses) is recorded
+//! into a ring buffer of the last ``DEPTH`` transactions. Without the feature, this module does
+//! not exist and the bus does not spend any time or memory on tracing.
+//!
+//! The buffer lives in the ``I2C_TRACE`` symbol, so it can be inspected from a debugger even when
+//! the firmware is stuck (eg. ``print I2C_TRACE`` in gdb), or printed by the application using
+//! ``dump``:
+//!
+//! ```ignore
+//! let mut stdout = cortex_m_semihosting::hio::hstdout().unwrap();
+//! thunderboard_sltb001a::i2c_trace::dump(&mut stdout).unwrap();
+//! ```
+//!
+//! Timestamps are raw RTCC ticks (see ``rtc::TICK_HZ``), or 0 if the RTCC is not running.
+
+use core::cell::RefCell;
+use core::fmt;
+
+use cortex_m::interrupt::{self, Mutex};
+use efr32xg1 as registers;
+use efm32gg_hal::i2c;
+
+use crate::i2c_bus::{Direction, Error};
+
+/// Number of transactions kept in the trace
+pub const DEPTH: usize = 32;
+
+/// Number of payload bytes kept per transaction; longer transfers are truncated.
+pub const DATA_LEN: usize = 8;
+
+/// How a transaction ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Ok,
+    AddressNack,
+    DataNack,
+    ArbitrationLost,
+    NotReady,
+    Timeout,
+    Busy,
+}
+
+impl From<&Result<(), Error>> for Outcome {
+    fn from(result: &Result<(), Error>) -> Self
+    {
+        match result {
+            Ok(()) => Outcome::Ok,
+            Err(Error::Bus(i2c::Error::AddressNack)) => Outcome::AddressNack,
+            Err(Error::Bus(i2c::Error::DataNack)) => Outcome::DataNack,
+            Err(Error::Bus(i2c::Error::ArbitrationLost)) => Outcome::ArbitrationLost,
+            Err(Error::Bus(i2c::Error::NotReady)) => Outcome::NotReady,
+            Err(Error::Timeout) => Outcome::Timeout,
+            Err(Error::Busy) => Outcome::Busy,
+        }
+    }
+}
+
+/// A recorded transaction
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    /// RTCC tick count at the end of the transaction
+    pub timestamp: u32,
+    /// 7-bit device address
+    pub address: u8,
+    pub direction: Direction,
+    /// Number of bytes written
+    pub written: u8,
+    /// Number of bytes read
+    pub read: u8,
+    /// The written bytes followed by the read bytes, as far as they fit
+    pub data: [u8; DATA_LEN],
+    /// Number of retries that were needed (or spent in vain)
+    pub retries: u8,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{:>10} {:02x} {:?} w{} r{} [", self.timestamp, self.address, self.direction, self.written, self.read)?;
+        let total = (usize::from(self.written) + usize::from(self.read)).min(DATA_LEN);
+        for (i, byte) in self.data[..total].iter().enumerate() {
+            if i == usize::from(self.written) {
+                write!(f, " |")?;
+            }
+            write!(f, " {:02x}", byte)?;
+        }
+        write!(f, " ] {:?}", self.outcome)?;
+        if self.retries > 0 {
+            write!(f, " after {} retries", self.retries)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ring buffer of transactions
+pub struct Trace {
+    entries: [Option<Entry>; DEPTH],
+    /// Index the next entry gets written to
+    next: usize,
+    /// Number of transactions recorded in total, including those that were overwritten already
+    pub total: u32,
+}
+
+impl Trace {
+    const fn new() -> Self
+    {
+        Trace { entries: [None; DEPTH], next: 0, total: 0 }
+    }
+
+    /// Iterate over the recorded entries, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Entry>
+    {
+        let (newer, older) = self.entries.split_at(self.next);
+        older.iter().chain(newer.iter()).filter_map(Option::as_ref)
+    }
+}
+
+/// The trace, for access by the debugger; use ``with`` or ``dump`` from the application.
+#[no_mangle]
+#[used]
+pub static I2C_TRACE: Mutex<RefCell<Trace>> = Mutex::new(RefCell::new(Trace::new()));
+
+fn timestamp() -> u32
+{
+    // unsafe: Only reading, and the RTCC only if it is clocked (otherwise the access would fault)
+    let cmu = unsafe { &*registers::CMU::ptr() };
+    let rtcc = unsafe { &*registers::RTCC::ptr() };
+    if cmu.hfbusclken0.read().le().bit_is_set() && cmu.lfeclken0.read().rtcc().bit_is_set() {
+        rtcc.cnt.read().cnt().bits()
+    } else {
+        0
+    }
+}
+
+/// Add a transaction to the trace; called by the bus.
+pub(crate) fn record(address: u8, direction: Direction, written: &[u8], read: &[u8], retries: u8, result: &Result<(), Error>)
+{
+    let mut data = [0; DATA_LEN];
+    for (d, s) in data.iter_mut().zip(written.iter().chain(read.iter())) {
+        *d = *s;
+    }
+
+    let entry = Entry {
+        timestamp: timestamp(),
+        address,
+        direction,
+        written: written.len().min(255) as u8,
+        read: read.len().min(255) as u8,
+        data,
+        retries,
+        outcome: result.into(),
+    };
+
+    interrupt::free(|cs| {
+        let mut trace = I2C_TRACE.borrow(cs).borrow_mut();
+        let next = trace.next;
+        trace.entries[next] = Some(entry);
+        trace.next = (next + 1) % DEPTH;
+        trace.total = trace.total.wrapping_add(1);
+    })
+}
+
+/// Run a closure on the trace.
+///
+/// The closure runs in a critical section, so nothing is recorded in the meantime.
+pub fn with<R>(f: impl FnOnce(&Trace) -> R) -> R
+{
+    interrupt::free(|cs| f(&I2C_TRACE.borrow(cs).borrow()))
+}
+
+/// Forget all recorded transactions.
+pub fn clear()
+{
+    interrupt::free(|cs| *I2C_TRACE.borrow(cs).borrow_mut() = Trace::new())
+}
+
+/// Write the trace in a human readable form, one line per transaction, oldest first.
+///
+/// The trace is copied before writing, so slow outputs (like semihosting) do not hold off
+/// interrupts.
+pub fn dump(out: &mut impl fmt::Write) -> fmt::Result
+{
+    let (entries, next, total) = with(|t| (t.entries, t.next, t.total));
+    let trace = Trace { entries, next, total };
+    writeln!(out, "I2C trace, {} transactions in total:", total)?;
+    for entry in trace.iter() {
+        writeln!(out, "{}", entry)?;
+    }
+    Ok(())
+}
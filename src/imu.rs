@@ -0,0 +1,78 @@
+//! Minimal access to the ICM-20648 inertial sensor
+//!
+//! The sensor is connected through SPI (once powered and connected by ``PIC::set_imu``). As the
+//! HAL does not provide SPI yet, the bus is bit-banged; that is slow, but sufficient for
+//! identifying the device and reading a few registers.
+//!
+//! The pins follow Silicon Labs' board support package for the BRD4160A: MOSI on PC0, MISO on
+//! PC1, SCLK on PC2 and CS on PC3.
+
+use efm32gg_hal::gpio;
+use efm32gg_hal::gpio::EFM32Pin;
+use embedded_hal::digital::{InputPin, OutputPin};
+
+/// Value of the WHO_AM_I register of an ICM-20648
+pub const WHO_AM_I: u8 = 0xe0;
+
+/// Half a clock period of the bit-banged SPI; about 2.5us at 19MHz, well within the device's 7MHz
+const HALF_PERIOD_CYCLES: u32 = 48;
+
+pub struct Imu {
+    mosi: gpio::pins::PC0<gpio::Output>,
+    miso: gpio::pins::PC1<gpio::Input>,
+    sclk: gpio::pins::PC2<gpio::Output>,
+    cs: gpio::pins::PC3<gpio::Output>,
+}
+
+impl Imu {
+    pub fn new(pc0: gpio::pins::PC0<gpio::Disabled>, pc1: gpio::pins::PC1<gpio::Disabled>, pc2: gpio::pins::PC2<gpio::Disabled>, pc3: gpio::pins::PC3<gpio::Disabled>) -> Self
+    {
+        let mut imu = Imu { mosi: pc0.as_output(), miso: pc1.as_input(), sclk: pc2.as_output(), cs: pc3.as_output() };
+        // SPI mode 3: clock idles high
+        imu.cs.set_high();
+        imu.sclk.set_high();
+        imu
+    }
+
+    /// Exchange a byte, MSB first, sampling on the rising edge
+    fn transfer(&mut self, mut out: u8) -> u8
+    {
+        let mut input = 0;
+        for _ in 0..8 {
+            self.sclk.set_low();
+            if out & 0x80 != 0 {
+                self.mosi.set_high();
+            } else {
+                self.mosi.set_low();
+            }
+            out <<= 1;
+            cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+            self.sclk.set_high();
+            input = (input << 1) | (self.miso.is_high() as u8);
+            cortex_m::asm::delay(HALF_PERIOD_CYCLES);
+        }
+        input
+    }
+
+    /// Read a register of the currently selected user bank (bank 0 after power-up).
+    pub fn read_register(&mut self, reg: u8) -> u8
+    {
+        self.cs.set_low();
+        self.transfer(0x80 | reg);
+        let result = self.transfer(0);
+        self.cs.set_high();
+        result
+    }
+
+    /// Read the device identification register, which should be ``WHO_AM_I``.
+    pub fn who_am_i(&mut self) -> u8
+    {
+        self.read_register(0x00)
+    }
+
+    /// Release the pins, eg. for use with a proper SPI implementation
+    pub fn free(self) -> (gpio::pins::PC0<gpio::Output>, gpio::pins::PC1<gpio::Input>, gpio::pins::PC2<gpio::Output>, gpio::pins::PC3<gpio::Output>)
+    {
+        (self.mosi, self.miso, self.sclk, self.cs)
+    }
+}
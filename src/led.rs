@@ -32,4 +32,23 @@ impl LEDs {
     {
         self.led1.set_low();
     }
+
+    /// Drive each LED pin both ways and check that the pin follows, which catches shorts of the
+    /// LED lines. The LEDs are left off.
+    pub(crate) fn self_check(&mut self) -> bool
+    {
+        // unsafe: Only reading the input state of the port
+        let gpio = unsafe { &*efr32xg1::GPIO::ptr() };
+        let pin_high = |pin: u32| gpio.pd_din.read().bits() & (1 << pin) != 0;
+
+        self.led0_on();
+        self.led1_off();
+        let first = pin_high(11) && !pin_high(12);
+        self.led0_off();
+        self.led1_on();
+        let second = !pin_high(11) && pin_high(12);
+        self.led1_off();
+
+        first && second
+    }
 }
@@ -0,0 +1,69 @@
+//! Low-frequency clock setup
+//!
+//! The efm32gg-hal CMU abstraction only covers the high frequency clocks so far, so the board
+//! support crate sets up the 32.768kHz crystal (LFXO) on its own for the peripherals that need to
+//! keep running in EM2 (LETIMER0 on the LFA branch, RTCC on the LFE branch).
+//!
+//! All of this writes into the CMU behind the HAL's back, like the HAL's own clock enable
+//! functions do (see the "UNSAFE FIXME"s there); the individual bits touched here are not used by
+//! the HAL.
+
+use efr32xg1 as registers;
+
+/// Frequency of the LFXO crystal on the board
+pub const LFXO_HZ: u32 = 32_768;
+
+fn cmu() -> &'static registers::cmu::RegisterBlock {
+    unsafe { &*registers::CMU::ptr() }
+}
+
+/// Start the LFXO (if it is not running already) and wait for it to become ready.
+///
+/// This can take several hundred milliseconds after power-up, so it is only done when a low
+/// frequency peripheral is actually started.
+pub(crate) fn enable_lfxo()
+{
+    let cmu = cmu();
+    if cmu.status.read().lfxordy().bit_is_set() {
+        return;
+    }
+    cmu.oscencmd.write(|w| w.lfxoen().set_bit());
+    while cmu.status.read().lfxordy().bit_is_clear() {}
+}
+
+/// Enable the clock to the low energy peripherals' register interface.
+pub(crate) fn enable_le_bus()
+{
+    // UNSAFE FIXME as with the HAL's clock enable functions: this is a read-modify-write on a
+    // register that is shared with the HAL's GPIO clock.
+    cortex_m::interrupt::free(|_| {
+        cmu().hfbusclken0.modify(|_, w| w.le().set_bit());
+    });
+}
+
+/// Feed LFXO into the LFA clock branch and enable it for LETIMER0 with the given prescaler
+/// exponent (the LETIMER0 then counts at ``LFXO_HZ >> presc``).
+pub(crate) fn enable_letimer0(presc: u8)
+{
+    enable_lfxo();
+    enable_le_bus();
+
+    let cmu = cmu();
+    cmu.lfaclksel.write(|w| w.lfa().lfxo());
+    while cmu.syncbusy.read().lfapresc0().bit_is_set() {}
+    cmu.lfapresc0.write(|w| w.letimer0().bits(presc));
+    while cmu.syncbusy.read().lfaclken0().bit_is_set() {}
+    cmu.lfaclken0.write(|w| w.letimer0().set_bit());
+}
+
+/// Feed LFXO into the LFE clock branch and enable it for the RTCC.
+pub(crate) fn enable_rtcc()
+{
+    enable_lfxo();
+    enable_le_bus();
+
+    let cmu = cmu();
+    cmu.lfeclksel.write(|w| w.lfe().lfxo());
+    while cmu.syncbusy.read().lfeclken0().bit_is_set() {}
+    cmu.lfeclken0.write(|w| w.rtcc().set_bit());
+}
@@ -45,9 +45,41 @@ pub mod led;
 pub mod led_pwm;
 pub mod button;
 pub mod pic;
+pub mod i2c_bus;
+#[cfg(feature = "i2c-trace")]
+pub mod i2c_trace;
+pub mod probe;
+pub mod imu;
+pub mod self_test;
+pub mod delay;
+pub mod time;
+pub mod timer;
+pub mod rtc;
+pub mod shared;
+pub mod exti;
+pub mod time_driver;
+pub mod executor;
+pub mod sleep;
+pub mod hibernate;
+pub mod power;
+pub mod battery;
+pub mod si7021;
+pub mod humidity;
+pub mod si1133;
+pub mod bmp280;
+pub mod barometer;
+pub mod variometer;
+pub mod ccs811;
+pub mod air_quality;
+#[cfg(feature = "rtic")]
+pub mod rtic;
+
+mod lfclock;
 
 use core::cell::RefCell;
 
+use cortex_m::interrupt::Mutex;
+
 use efm32gg_hal::{
     gpio::GPIOExt,
     cmu::CMUExt,
@@ -72,22 +104,35 @@ pub struct Board<D1, D2>
     pub leds: led_pwm::LEDs,
     pub buttons: button::Buttons,
     pub delay: D1,
-    pub pic: pic::PIC<D2>,
+    pub pic: pic::PIC<D2, i2c_bus::I2cProxy<'static>>,
+    /// The I2C bus shared by the PIC and the sensors; call ``.acquire()`` on it to get a proxy for
+    /// a sensor driver.
+    pub i2c_bus: &'static i2c_bus::I2cBus,
+    /// The inertial sensor; it needs to be powered through ``pic.set_imu(true)`` to be usable.
+    pub imu: imu::Imu,
+    /// Supply voltage measurement, using ADC0
+    pub battery: battery::BatteryMonitor,
 
     // Assorted peripherals not used by the various abstractions
 
     // From the Cortex-M part
     pub nvic: cortex_m::peripheral::NVIC,
 
-    // Part from the EFM HAL, ready with their clock enabled
-    // (Still considering handing them out uninitialized, but then all their CMU parts would need
-    // to go out as well)
-    pub timer1: efm32gg_hal::timer::Timer1,
+    // Timers, ready with their clock enabled (or, for the low-frequency ones, with the clock
+    // enabled when they are started)
+    pub timer1: timer::Timer1,
+    pub letimer0: timer::LETimer0,
+
+    // Clock that has been running since board initialization
+    pub rtc: rtc::Rtc,
+
+    /// Whether this start continues an EM4 hibernation, and the state stored for it
+    pub boot: hibernate::Boot,
 
     // GPIO pins. (None needed yet; in the end, this should include all the connectors).
 }
 
-impl Board<RefCellDelay, RefCellDelay> {
+impl Board<delay::SharedDelay, delay::SharedDelay> {
     /// Initialize the board
     ///
     /// This does little configuration, but primarily ``take``s the system and EFM32 peripherals and
@@ -118,38 +163,94 @@ impl Board<RefCellDelay, RefCellDelay> {
         let syst = corep.SYST.constrain();
         // I'd prefer to have the delay mutex just live in the board struct and then deal
         // references out (won't work for lifetime reasons).
-        let delay = &*singleton!(: RefCell<SystickDelay> = RefCell::new(SystickDelay::new(syst, hfcoreclk))).unwrap();
+        let delay = &*singleton!(: Mutex<RefCell<SystickDelay>> = Mutex::new(RefCell::new(SystickDelay::new(syst, hfcoreclk)))).unwrap();
 
-        // At board initialization, it makes sense to clear the LEDs because the EFM8 is not reset
-        // along with the EFR32. (Would make sense to clear everything else too once enabled, or to
-        // find a SYS_CMD that resets the chip as a whole, see
-        // <https://www.silabs.com/community/thunderboard/forum.topic.html/thunderboard_reset-6Agl>).
-        let mut pic = pic::PIC::new(p.I2C0, RefCellDelay::new(delay), cmu.i2c0, gpios.pd10, gpios.pc11, gpios.pc10);
-        pic.set_leds(false, false, false, false);
-        let id = pic.read_device_id();
-        assert!(&id == &[0x49, 0x4f, 0x58, 0x50], "PIC device ID unexpected");
+        let i2c = i2c_bus::configure(p.I2C0, cmu.i2c0, gpios.pc11, gpios.pc10);
+        let i2c_bus = &*singleton!(: i2c_bus::I2cBus = i2c_bus::I2cBus::new(i2c)).unwrap();
 
-        let timer1 = p.TIMER1.with_clock(cmu.timer1);
+        let mut pic = pic::PIC::with_i2c(i2c_bus.acquire(), delay::SharedDelay::new(delay), gpios.pd10);
+        reset_pic(&mut pic);
+
+        let imu = imu::Imu::new(gpios.pc0, gpios.pc1, gpios.pc2, gpios.pc3);
+        let battery = battery::BatteryMonitor::new(p.ADC0);
+
+        let timer1 = timer::Timer1::new(p.TIMER1.with_clock(cmu.timer1));
+        let letimer0 = timer::LETimer0::new(p.LETIMER0);
+        let mut rtc = rtc::Rtc::new(p.RTCC);
+        let boot = hibernate::boot(&mut rtc);
 
         Board {
             leds: leds,
             buttons: buttons,
-            delay: RefCellDelay::new(delay),
+            delay: delay::SharedDelay::new(delay),
             pic: pic,
+            i2c_bus: i2c_bus,
+            imu: imu,
+            battery: battery,
 
             nvic: corep.NVIC,
 
             timer1: timer1,
+            letimer0: letimer0,
+
+            rtc: rtc,
+
+            boot: boot,
         }
     }
 }
 
+impl<D1, D2> Board<D1, D2>
+    where D1: embedded_hal::blocking::delay::DelayMs<u16>,
+          D2: embedded_hal::blocking::delay::DelayUs<u16>,
+{
+    /// Power the given PIC domains and find out which devices respond on the I2C bus.
+    ///
+    /// See the ``probe`` module for details.
+    pub fn probe_i2c(&mut self, domains: probe::Domains) -> probe::Inventory
+    {
+        probe::probe(&mut self.pic, self.i2c_bus, &mut self.delay, domains)
+    }
+
+    /// Check that all the board's components are present and responsive.
+    ///
+    /// This switches the PIC domains on one at a time, and leaves them all switched off; see the
+    /// ``self_test`` module for details.
+    pub fn self_test(&mut self) -> self_test::Report
+    {
+        #[cfg(not(feature = "led-pwm"))]
+        let leds = self_test::Status::from(self.leds.self_check());
+        // The PWM timer drives the pins, so the levels can't be checked statically
+        #[cfg(feature = "led-pwm")]
+        let leds = self_test::Status::Skipped;
+
+        self_test::run(&mut self.pic, self.i2c_bus, &mut self.delay, &mut self.imu, &self.buttons, leds)
+    }
+}
+
+/// Bring the PIC into a defined state at board initialization, and check that it is there.
+fn reset_pic<D, I, E>(pic: &mut pic::PIC<D, I>)
+    where D: embedded_hal::blocking::delay::DelayUs<u16>,
+          I: embedded_hal::blocking::i2c::Write<Error = E> + embedded_hal::blocking::i2c::Read<Error = E>,
+          E: core::fmt::Debug,
+{
+    // At board initialization, it makes sense to clear the LEDs because the EFM8 is not reset
+    // along with the EFR32. (Would make sense to clear everything else too once enabled, or to
+    // find a SYS_CMD that resets the chip as a whole, see
+    // <https://www.silabs.com/community/thunderboard/forum.topic.html/thunderboard_reset-6Agl>).
+    pic.set_leds(false, false, false, false).expect("PIC not responding");
+    let id = pic.read_device_id().expect("PIC not responding");
+    assert!(&id == &[0x49, 0x4f, 0x58, 0x50], "PIC device ID unexpected");
+}
 
 
 
 // Needs its own type wrappe for two reasons:
 // a) I can only implement traits for own types here.
 // b) the delay functions need a mutable reference.
+//
+// The board does not use this any more, as the borrow_mut panics if an interrupt delays while
+// the main loop is delaying; see delay::SharedDelay for what is used instead.
 pub struct RefCellDelay {
     cell: &'static RefCell<SystickDelay>,
 }
@@ -1,7 +1,17 @@
 //! Access to the EFM8SB chip on the board, the Power and Interrupt Controller. That chip is
 //! basically used as a port expander, and is thus primarily exposed as GPIO output pins.
 //!
-//! (There are no concrete plans yet as to how its use as of forwarding interrupts can be exposed).
+//! Interrupts it forwards from the sensors can be awaited using ``wait_for_interrupt``, which
+//! needs the GPIO interrupts set up as described in the ``exti`` module.
+//!
+//! The PIC can own the I2C0 peripheral exclusively, or (as set up by the board) share it with the
+//! sensors through an ``i2c_bus::I2cProxy``. All accesses report the bus' errors; on a shared bus,
+//! that includes ``i2c_bus::Error::Busy`` when the PIC is used from an interrupt handler that
+//! interrupted another transaction.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 
 use embedded_hal::blocking::delay::DelayUs;
 use efr32xg1 as registers;
@@ -9,20 +19,23 @@ use efm32gg_hal::i2c::ConfiguredI2C0;
 use efm32gg_hal::cmu::I2C0Clk;
 use efm32gg_hal::gpio::{Disabled, Output};
 use efm32gg_hal::gpio::pins::{PD10, PC11, PC10};
+use efm32gg_hal::gpio::EFM32Pin;
 
 use embedded_hal::digital::OutputPin;
 use embedded_hal::blocking::i2c::{Write, Read};
 
-pub struct PIC<D>
+use crate::{exti, i2c_bus};
+
+pub struct PIC<D, I = ConfiguredI2C0>
 {
-    i2c: ConfiguredI2C0,
+    i2c: I,
     delay: D,
     int_wake: PD10<Output>,
 }
 
 const ADDR: u8 = 0x48;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct InterruptSet {
     pub ccs: bool,
     pub imu: bool,
@@ -30,7 +43,7 @@ pub struct InterruptSet {
 }
 
 impl InterruptSet {
-    fn to_bits(&self) -> u8 {
+    fn to_bits(self) -> u8 {
         ((self.ccs as u8) << 0) | ((self.imu as u8) << 1) | ((self.uv as u8) << 2)
     }
 
@@ -71,19 +84,31 @@ impl<D> PIC<D>
 {
     pub fn new(register: registers::I2C0, delay: D, clk: I2C0Clk, pd10: PD10<Disabled>, pc11: PC11<Disabled>, pc10: PC10<Disabled>) -> Self
     {
-        use efm32gg_hal::gpio::EFM32Pin;
-        use efm32gg_hal::i2c::I2CExt;
-
-        let i2c = register.with_clock(clk).with_scl(registers::i2c0::routeloc0::SCLLOCW::LOC15, pc11).unwrap().with_sda(registers::i2c0::routeloc0::SDALOCW::LOC15, pc10).unwrap();
+        let i2c = i2c_bus::configure(register, clk, pc11, pc10);
+        Self::with_i2c(i2c, delay, pd10)
+    }
+}
 
+impl<D, I, E> PIC<D, I>
+    where D: DelayUs<u16>,
+          I: Write<Error = E> + Read<Error = E>,
+          E: core::fmt::Debug,
+{
+    /// Create a PIC abstraction on an I2C bus that is configured already (typically a proxy to
+    /// a shared bus).
+    pub fn with_i2c(i2c: I, delay: D, pd10: PD10<Disabled>) -> Self
+    {
         let mut int_wake = pd10.as_opendrain();
         int_wake.set_high();
 
         PIC { i2c: i2c, delay: delay, int_wake: int_wake }
     }
 
-    fn acquiring<T>(&mut self, inner: impl FnOnce(&mut ConfiguredI2C0) -> T) -> T
+    fn acquiring<T>(&mut self, inner: impl FnOnce(&mut I) -> T) -> T
     {
+        // The INT line is shared with WAKE, so our own wake-up looks like an interrupt
+        let was_armed = exti::suspend(exti::LINE_PIC);
+
         self.int_wake.set_low();
         self.delay.delay_us(5u16);
 
@@ -93,65 +118,65 @@ impl<D> PIC<D>
         // send a pulse and i2c right away (i can't be too slow, can i?)
         self.int_wake.set_high();
 
+        exti::resume(exti::LINE_PIC, was_armed);
+
         result
     }
 
-    fn set_register(&mut self, reg: u8, value: u8)
+    fn set_register(&mut self, reg: u8, value: u8) -> Result<(), E>
     {
-        self.acquiring(|i2c| {
-            i2c.write(ADDR, &[reg, value]).unwrap();
-        })
+        self.acquiring(|i2c| i2c.write(ADDR, &[reg, value]))
     }
 
     /// Enable or disable (ie. set power and connect SPI) the inertial sensor
-    pub fn set_imu(&mut self, enable: bool)
+    pub fn set_imu(&mut self, enable: bool) -> Result<(), E>
     {
-        self.set_register(0x01, enable as u8);
+        self.set_register(0x00, enable as u8)
     }
 
     /// Enable or disable (ie. set power and connect I2C) the environmental sensor group
-    pub fn set_env_sensor(&mut self, enable: bool)
+    pub fn set_env_sensor(&mut self, enable: bool) -> Result<(), E>
     {
-        self.set_register(0x01, enable as u8);
+        self.set_register(0x01, enable as u8)
     }
 
     /// Enable or disable the microphone
-    pub fn set_mic(&mut self, enable: bool)
+    pub fn set_mic(&mut self, enable: bool) -> Result<(), E>
     {
-        self.set_register(0x02, enable as u8);
+        self.set_register(0x02, enable as u8)
     }
 
     /// Enable or disable the indoor air quality sensor (ie. set power and connect I2C at 0x5a), and set its wake state
-    pub fn set_ccs(&mut self, enable: bool, wake: bool)
+    pub fn set_ccs(&mut self, enable: bool, wake: bool) -> Result<(), E>
     {
         let state = (enable as u8) | ((wake as u8) << 1);
-        self.set_register(0x03, state);
+        self.set_register(0x03, state)
     }
 
     /// Enable or disable the individual RGB LEDs.
     ///
     /// This only provides power to the LEDs; a color still needs to be set using the LED pins of
     /// the main MCU.
-    pub fn set_leds(&mut self, led0: bool, led1: bool, led2: bool, led3: bool)
+    pub fn set_leds(&mut self, led0: bool, led1: bool, led2: bool, led3: bool) -> Result<(), E>
     {
         let led_config: u8 = ((led0 as u8) << 7) |
                              ((led1 as u8) << 6) |
                              ((led2 as u8) << 5) |
                              ((led3 as u8) << 4) |
                              ((led0 || led1 || led2 || led3) as u8);
-        self.set_register(0x04, led_config);
+        self.set_register(0x04, led_config)
     }
 
     /// Select which interrupts are active
-    pub fn set_int(&mut self, enable: InterruptSet)
+    pub fn set_int(&mut self, enable: InterruptSet) -> Result<(), E>
     {
-        self.set_register(0x05, enable.to_bits());
+        self.set_register(0x05, enable.to_bits())
     }
 
     /// Selectively clear the pending interrupts
-    pub fn clear_int(&mut self, clear: InterruptSet)
+    pub fn clear_int(&mut self, clear: InterruptSet) -> Result<(), E>
     {
-        self.set_register(0x06, clear.to_bits());
+        self.set_register(0x06, clear.to_bits())
     }
 
     /// Query which interrupts are active (CCS, IMU or UV)
@@ -160,50 +185,104 @@ impl<D> PIC<D>
     /// EFM8SB chip. As long as its wake state is not tracked (in which case it might be possible
     /// to directly read after an interrupt), it might be faster to just check the individual
     /// active devices for any pending interrupt causes.
-    pub fn pending_int(&mut self) -> InterruptSet
+    pub fn pending_int(&mut self) -> Result<InterruptSet, E>
     {
         self.acquiring(|i2c| {
             let mut result = [0xff; 1];
-            i2c.write(ADDR, &[0x07]).unwrap();
-            i2c.read(ADDR, &mut result).unwrap();
-            InterruptSet::from_bits(result[0])
+            i2c.write(ADDR, &[0x07])?;
+            i2c.read(ADDR, &mut result)?;
+            Ok(InterruptSet::from_bits(result[0]))
         })
     }
 
     /// Configure the interrupt controller settings
-    pub fn set_int_mode(&mut self, mode: &InterruptConfiguration)
+    pub fn set_int_mode(&mut self, mode: &InterruptConfiguration) -> Result<(), E>
     {
-        self.set_register(0x08, mode.to_bits());
+        self.set_register(0x08, mode.to_bits())
     }
 
     /// Read the (major, minor, patch) version components of the PIC firmware version
-    pub fn read_firmware_version(&mut self) -> [u8; 3]
+    pub fn read_firmware_version(&mut self) -> Result<[u8; 3], E>
     {
         self.acquiring(|i2c| {
             let mut result = [0xf1; 3];
             for i in 0..3 {
-                i2c.write(ADDR, &[0xf8 + i]).unwrap();
-                i2c.read(ADDR, &mut result[(i as usize)..((i+1) as usize)]).unwrap();
+                i2c.write(ADDR, &[0xf8 + i])?;
+                i2c.read(ADDR, &mut result[(i as usize)..((i+1) as usize)])?;
             }
-            result
+            Ok(result)
         })
     }
 
     /// Read the 4-byte device identification number
-    pub fn read_device_id(&mut self) -> [u8; 4]
+    pub fn read_device_id(&mut self) -> Result<[u8; 4], E>
     {
         self.acquiring(|i2c| {
             let mut result = [0xff; 4];
             for i in 0..4 {
-                i2c.write(ADDR, &[0xf8 + i]).unwrap();
-                i2c.read(ADDR, &mut result[(i as usize)..((i+1) as usize)]).unwrap();
+                i2c.write(ADDR, &[0xf8 + i])?;
+                i2c.read(ADDR, &mut result[(i as usize)..((i+1) as usize)])?;
             }
-            result
+            Ok(result)
         })
     }
 
-    pub fn destroy(self) -> (ConfiguredI2C0, D)
+    /// Wait for the PIC to signal an interrupt on its INT line, and report which are pending.
+    ///
+    /// In the default ``SinglePulse`` interrupt mode, only interrupts that arrive after the first
+    /// poll are noticed. With ``Latched``, an interrupt that is pending already completes the
+    /// future right away, so that mode is recommended when waiting in a loop.
+    ///
+    /// The pending interrupts are not cleared; that is left to ``clear_int`` once the sources
+    /// have been serviced.
+    pub fn wait_for_interrupt(&mut self) -> WaitForInterrupt<'_, D, I>
+    {
+        WaitForInterrupt { pic: self, armed: false }
+    }
+
+    pub fn destroy(self) -> (I, D)
     {
         (self.i2c, self.delay)
     }
 }
+
+/// Future returned by ``PIC::wait_for_interrupt``
+pub struct WaitForInterrupt<'a, D, I> {
+    pic: &'a mut PIC<D, I>,
+    armed: bool,
+}
+
+impl<D, I, E> Future for WaitForInterrupt<'_, D, I>
+    where D: DelayUs<u16>,
+          I: Write<Error = E> + Read<Error = E>,
+          E: core::fmt::Debug,
+{
+    type Output = Result<InterruptSet, E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output>
+    {
+        if !self.armed {
+            exti::configure();
+            exti::arm(exti::LINE_PIC);
+            self.armed = true;
+        }
+
+        // Checking the level too catches latched interrupts that were there before arming
+        if exti::poll_fired(exti::LINE_PIC, cx.waker()) || exti::is_low(exti::LINE_PIC) {
+            exti::disarm(exti::LINE_PIC);
+            self.armed = false;
+            Poll::Ready(self.pic.pending_int())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<D, I> Drop for WaitForInterrupt<'_, D, I> {
+    fn drop(&mut self)
+    {
+        if self.armed {
+            exti::disarm(exti::LINE_PIC);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Shared use of the PIC's power domains
+//!
+//! The PIC switches power to groups of devices (see ``Domain``), and several drivers may need the
+//! same domain -- the Si7021, Si1133 and BMP280 drivers all need the environmental sensor domain.
+//! The ``PowerManager`` takes over the PIC and counts the users of each domain: A domain is
+//! powered when the first user acquires it, and switched off when the last user releases it.
+//!
+//! ```ignore
+//! let mut power = PowerManager::new(board.pic)?;
+//! let env = power.acquire(Domain::EnvSensor)?;
+//! // ... use the sensors ...
+//! power.release(env)?;
+//! ```
+//!
+//! Switching reports the errors of the PIC's bus; the counts stay consistent with the domains'
+//! intended state either way.
+//!
+//! The manager also keeps a rough estimate of the current the powered domains draw, based on
+//! typical datasheet figures. The application can replace those with measured values using
+//! ``set_estimate``.
+
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::pic::PIC;
+
+/// A group of devices the PIC switches power to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Domain {
+    /// The ICM-20648 inertial sensor
+    Imu,
+    /// The Si7021, Si1133 and BMP280 sensors
+    EnvSensor,
+    /// The microphone
+    Mic,
+    /// The CCS811 indoor air quality sensor
+    Ccs,
+    /// The four RGB LEDs
+    Leds,
+}
+
+const DOMAINS: usize = 5;
+
+impl Domain {
+    /// All domains
+    pub const ALL: [Domain; DOMAINS] = [Domain::Imu, Domain::EnvSensor, Domain::Mic, Domain::Ccs, Domain::Leds];
+
+    fn index(self) -> usize
+    {
+        match self {
+            Domain::Imu => 0,
+            Domain::EnvSensor => 1,
+            Domain::Mic => 2,
+            Domain::Ccs => 3,
+            Domain::Leds => 4,
+        }
+    }
+}
+
+/// Typical current per domain in microamperes, in the order of ``Domain::index``
+///
+/// These are rough figures for continuous operation (eg. the CCS811 in its 1s measurement mode,
+/// the LEDs at moderate brightness); idle sensors draw much less.
+const DEFAULT_ESTIMATES_UA: [u32; DOMAINS] = [3_000, 500, 150, 1_200, 10_000];
+
+/// Proof of having acquired a domain, to be handed back to ``PowerManager::release``
+#[must_use = "The domain stays powered until the lease is released"]
+#[derive(Debug)]
+pub struct Lease {
+    domain: Domain,
+}
+
+impl Lease {
+    pub fn domain(&self) -> Domain
+    {
+        self.domain
+    }
+}
+
+pub struct PowerManager<D, I> {
+    pic: PIC<D, I>,
+    users: [u8; DOMAINS],
+    estimates_ua: [u32; DOMAINS],
+    ccs_wake: bool,
+}
+
+impl<D, I, E> PowerManager<D, I>
+    where D: DelayUs<u16>,
+          I: Write<Error = E> + Read<Error = E>,
+          E: core::fmt::Debug,
+{
+    /// Take over the PIC, switching all domains off.
+    pub fn new(pic: PIC<D, I>) -> Result<Self, E>
+    {
+        let mut manager = PowerManager { pic, users: [0; DOMAINS], estimates_ua: DEFAULT_ESTIMATES_UA, ccs_wake: false };
+        for domain in Domain::ALL.iter() {
+            manager.switch(*domain, false)?;
+        }
+        Ok(manager)
+    }
+
+    fn switch(&mut self, domain: Domain, on: bool) -> Result<(), E>
+    {
+        match domain {
+            Domain::Imu => self.pic.set_imu(on),
+            Domain::EnvSensor => self.pic.set_env_sensor(on),
+            Domain::Mic => self.pic.set_mic(on),
+            Domain::Ccs => {
+                self.ccs_wake &= on;
+                self.pic.set_ccs(on, self.ccs_wake)
+            }
+            Domain::Leds => self.pic.set_leds(on, on, on, on),
+        }
+    }
+
+    /// Register as a user of the domain, powering it if it was off.
+    ///
+    /// Devices need some time after being powered before they respond; that is up to their
+    /// drivers.
+    pub fn acquire(&mut self, domain: Domain) -> Result<Lease, E>
+    {
+        let users = &mut self.users[domain.index()];
+        *users = users.checked_add(1).expect("Too many users of a power domain");
+        if *users == 1 {
+            if let Err(e) = self.switch(domain, true) {
+                self.users[domain.index()] = 0;
+                return Err(e);
+            }
+        }
+        Ok(Lease { domain })
+    }
+
+    /// Give up a domain, switching it off if this was the last user.
+    ///
+    /// If switching off fails, the lease is given up nevertheless, and the domain is switched
+    /// again with its next use.
+    pub fn release(&mut self, lease: Lease) -> Result<(), E>
+    {
+        let users = &mut self.users[lease.domain.index()];
+        // Can't underflow: Every lease was counted when it was issued
+        *users -= 1;
+        if *users == 0 {
+            self.switch(lease.domain, false)?;
+        }
+        Ok(())
+    }
+
+    /// Set the CCS811's wake line (active while ``wake`` is true), which it needs asserted for
+    /// I2C communication. The domain itself must be held through a lease.
+    pub fn set_ccs_wake(&mut self, lease: &Lease, wake: bool) -> Result<(), E>
+    {
+        assert!(lease.domain == Domain::Ccs, "Lease is not for the CCS domain");
+        self.pic.set_ccs(true, wake)?;
+        self.ccs_wake = wake;
+        Ok(())
+    }
+
+    /// Number of current users of the domain
+    pub fn users(&self, domain: Domain) -> u8
+    {
+        self.users[domain.index()]
+    }
+
+    pub fn is_powered(&self, domain: Domain) -> bool
+    {
+        self.users(domain) > 0
+    }
+
+    /// Replace the current estimate for a domain (eg. with a measured value), in microamperes.
+    pub fn set_estimate(&mut self, domain: Domain, microamps: u32)
+    {
+        self.estimates_ua[domain.index()] = microamps;
+    }
+
+    /// Estimated current drawn by the domain now, in microamperes (0 if it is off)
+    pub fn domain_current_ua(&self, domain: Domain) -> u32
+    {
+        if self.is_powered(domain) {
+            self.estimates_ua[domain.index()]
+        } else {
+            0
+        }
+    }
+
+    /// Estimated current drawn by all powered domains, in microamperes
+    ///
+    /// This does not include the MCU itself and the PIC.
+    pub fn estimated_current_ua(&self) -> u32
+    {
+        Domain::ALL.iter()
+            .map(|d| self.domain_current_ua(*d))
+            .sum()
+    }
+
+    /// Access the PIC, eg. for its interrupt functions.
+    ///
+    /// Switching domains directly through it confuses the manager's bookkeeping.
+    pub fn pic(&mut self) -> &mut PIC<D, I>
+    {
+        &mut self.pic
+    }
+
+    /// Give the PIC back, leaving the domains in their current state.
+    pub fn free(self) -> PIC<D, I>
+    {
+        self.pic
+    }
+}
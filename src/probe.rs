@@ -0,0 +1,171 @@
+//! Finding out which devices respond on the I2C bus
+//!
+//! The PIC switches power to the sensors, so a device is only found if its domain is powered.
+//! ``probe`` powers the requested domains, scans all regular 7-bit addresses, and identifies the
+//! devices the Thunderboard Sense is known to carry by their ID registers:
+//!
+//! ```ignore
+//! let inventory = board.probe_i2c(probe::Domains { env_sensor: true, ccs: true });
+//! assert!(inventory.address_of(probe::Device::Si7021).is_some());
+//! for found in inventory.iter() { ... }
+//! ```
+//!
+//! The domains are left powered after the scan, so the found devices can be used right away.
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, WriteRead};
+
+use crate::i2c_bus::{I2cBus, I2cProxy, RetryPolicy};
+use crate::pic::PIC;
+
+/// The PIC's address; it is not scanned (as it only responds after a wake-up pulse), but asked
+/// through the PIC abstraction.
+const PIC_ADDR: u8 = 0x48;
+
+/// Time the sensors take to become responsive after being powered (the Si7021's 80ms, rounded up)
+const POWER_UP_MS: u16 = 100;
+
+/// Maximum number of responders the inventory can hold
+pub const CAPACITY: usize = 16;
+
+/// Power domains of the PIC that can be switched on before a scan
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Domains {
+    /// Si7021, Si1133 and BMP280
+    pub env_sensor: bool,
+    /// CCS811 (which is also woken up for the scan)
+    pub ccs: bool,
+}
+
+/// Kinds of devices that can be identified
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Device {
+    /// The board's power and interrupt controller
+    Pic,
+    /// Relative humidity and temperature sensor
+    Si7021,
+    /// UV index and ambient light sensor
+    Si1133,
+    /// Barometric pressure sensor
+    Bmp280,
+    /// Indoor air quality sensor
+    Ccs811,
+    /// A device that acknowledged its address, but could not be identified
+    Unknown,
+}
+
+/// A device that responded on the bus
+#[derive(Clone, Copy, Debug)]
+pub struct Found {
+    pub address: u8,
+    pub device: Device,
+}
+
+/// Result of a bus scan
+#[derive(Clone, Copy, Debug)]
+pub struct Inventory {
+    found: [Option<Found>; CAPACITY],
+    /// Set if more devices responded than the inventory could hold
+    pub overflow: bool,
+}
+
+impl Inventory {
+    fn new() -> Self
+    {
+        Inventory { found: [None; CAPACITY], overflow: false }
+    }
+
+    fn push(&mut self, found: Found)
+    {
+        match self.found.iter_mut().find(|f| f.is_none()) {
+            Some(slot) => *slot = Some(found),
+            None => self.overflow = true,
+        }
+    }
+
+    /// Iterate over the responders in order of their addresses (the PIC being first).
+    pub fn iter(&self) -> impl Iterator<Item = &Found>
+    {
+        self.found.iter().filter_map(Option::as_ref)
+    }
+
+    /// The address of the first responder that was identified as the given device
+    pub fn address_of(&self, device: Device) -> Option<u8>
+    {
+        self.iter().find(|f| f.device == device).map(|f| f.address)
+    }
+
+    /// Iterate over the addresses of responders that could not be identified.
+    pub fn unknown(&self) -> impl Iterator<Item = u8> + '_
+    {
+        self.iter().filter(|f| f.device == Device::Unknown).map(|f| f.address)
+    }
+}
+
+/// Read a device's ID register (or register sequence), giving None on any error.
+fn read_id<const N: usize>(i2c: &mut I2cProxy<'_>, addr: u8, command: &[u8]) -> Option<[u8; N]>
+{
+    let mut buf = [0; N];
+    i2c.write_read(addr, command, &mut buf).ok().map(|()| buf)
+}
+
+/// Find out which device is behind an address that responded.
+pub(crate) fn identify(i2c: &mut I2cProxy<'_>, addr: u8) -> Device
+{
+    match addr {
+        // Electronic ID, second part: SNB_3 is the device type
+        0x40 if read_id::<1>(i2c, addr, &[0xfc, 0xc9]) == Some([0x15]) => Device::Si7021,
+        // PART_ID
+        0x55 if read_id::<1>(i2c, addr, &[0x00]) == Some([0x33]) => Device::Si1133,
+        // id register
+        0x76 | 0x77 if read_id::<1>(i2c, addr, &[0xd0]) == Some([0x58]) => Device::Bmp280,
+        // HW_ID
+        0x5a | 0x5b if read_id::<1>(i2c, addr, &[0x20]) == Some([0x81]) => Device::Ccs811,
+        _ => Device::Unknown,
+    }
+}
+
+/// Power the requested domains, and scan the bus for devices.
+///
+/// The bus' retry policy is temporarily changed not to retry missing acknowledgements (which
+/// would only slow the scan down), and restored afterwards.
+pub fn probe<D, DM>(pic: &mut PIC<D, I2cProxy<'_>>, bus: &I2cBus, delay: &mut DM, domains: Domains) -> Inventory
+    where D: DelayUs<u16>,
+          DM: DelayMs<u16>,
+{
+    // Domains that fail to switch on just show no devices
+    if domains.env_sensor {
+        let _ = pic.set_env_sensor(true);
+    }
+    if domains.ccs {
+        let _ = pic.set_ccs(true, true);
+    }
+    if domains.env_sensor || domains.ccs {
+        delay.delay_ms(POWER_UP_MS);
+    }
+
+    let mut inventory = Inventory::new();
+
+    if pic.read_device_id().ok() == Some([0x49, 0x4f, 0x58, 0x50]) {
+        inventory.push(Found { address: PIC_ADDR, device: Device::Pic });
+    }
+
+    let policy = bus.policy();
+    bus.set_policy(RetryPolicy { retry_on_nack: false, ..policy });
+
+    let mut i2c = bus.acquire();
+    // Addresses outside this range are reserved
+    for addr in 0x08..0x78 {
+        if addr == PIC_ADDR {
+            continue;
+        }
+        let mut buf = [0];
+        if i2c.read(addr, &mut buf).is_ok() {
+            inventory.push(Found { address: addr, device: identify(&mut i2c, addr) });
+        }
+    }
+
+    bus.set_policy(policy);
+
+    inventory
+}
@@ -0,0 +1,375 @@
+//! Timekeeping using the RTCC (Real Time Counter and Calendar)
+//!
+//! The RTCC is clocked from the board's 32.768kHz crystal and keeps counting through EM2 and EM3,
+//! so unlike the SysTick it can tell how much time passed while the device was sleeping.
+//!
+//! The counter is run in its normal (non-calendar) mode at 1024 ticks per second; its 32 bits are
+//! extended to 64 bits in software by counting overflows. An overflow happens every 48.5 days,
+//! and is only noticed the next time the time is read -- so either read the time at least that
+//! often (any sensor loop will), or enable the RTCC interrupt with ``listen_overflow`` and call
+//! ``uptime_ticks`` from its handler.
+//!
+//! Wall clock time is not kept in hardware: when the host tells the device what time it is, the
+//! offset to the uptime counter is stored, and calendar times are calculated from that. Calendar
+//! alarms are implemented on top of that using the RTCC's compare channel 1, whose interrupt can
+//! wake the device from EM2.
+
+use efr32xg1 as registers;
+
+use crate::lfclock;
+use crate::time::Seconds;
+
+/// Number of RTCC ticks per second
+pub const TICK_HZ: u32 = 1024;
+
+/// Number of 32-bit retention registers, which keep their content through EM4 hibernation
+pub(crate) const RETAINED_WORDS: usize = 32;
+
+/// A calendar date and time of day in UTC
+///
+/// This is deliberately simple: It covers the proleptic Gregorian calendar from 1970 on and does
+/// not know about leap seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// Month, 1 to 12
+    pub month: u8,
+    /// Day of the month, 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Build a DateTime from seconds since the Unix epoch
+    pub fn from_unix(seconds: u64) -> Self
+    {
+        // Days-to-civil algorithm from <http://howardhinnant.github.io/date_algorithms.html>,
+        // restricted to dates after the epoch so everything can stay unsigned.
+        let days = seconds / 86400;
+        let secs_of_day = (seconds % 86400) as u32;
+
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + (month <= 2) as u64) as u16;
+
+        DateTime {
+            year, month, day,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+        }
+    }
+
+    /// Express the DateTime in seconds since the Unix epoch
+    ///
+    /// This panics for dates before 1970.
+    pub fn to_unix(&self) -> u64
+    {
+        assert!(self.year >= 1970, "Dates before the epoch are not supported");
+
+        let (month, day) = (u64::from(self.month), u64::from(self.day));
+        let year = u64::from(self.year) - (month <= 2) as u64;
+        let era = year / 400;
+        let yoe = year - era * 400;
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        days * 86400 + u64::from(self.hour) * 3600 + u64::from(self.minute) * 60 + u64::from(self.second)
+    }
+}
+
+/// Reasons why an alarm can not be set
+#[derive(Debug)]
+pub enum AlarmError {
+    /// The wall clock was not set yet, so calendar times can't be related to the counter.
+    NoWallClock,
+    /// The requested time has already passed.
+    InPast,
+}
+
+/// The RTCC based clock of the board
+pub struct Rtc {
+    register: registers::RTCC,
+    /// Number of counter overflows seen so far, ie. the upper 32 bits of the tick count
+    overflows: u32,
+    /// Tick count at the Unix epoch, relative to the counter (wrapping, as the device usually
+    /// booted well after the epoch)
+    epoch_offset: Option<u64>,
+    /// Tick count at which the current alarm is due
+    alarm: Option<u64>,
+}
+
+impl Rtc {
+    /// Start the RTCC from zero.
+    ///
+    /// This blocks until the LFXO is running, which can take a few hundred milliseconds after
+    /// power-up.
+    pub fn new(register: registers::RTCC) -> Self
+    {
+        lfclock::enable_rtcc();
+
+        register.ctrl.write(|w| w.enable().clear_bit());
+        register.cnt.write(|w| unsafe { w.cnt().bits(0) });
+        register.precnt.write(|w| unsafe { w.precnt().bits(0) });
+        register.ifc.write(|w| unsafe { w.bits(0xffff_ffff) });
+        register.cc1_ctrl.write(|w| w.mode().outputcompare());
+        register.ctrl.write(|w| w
+            .cntpresc().div32()
+            // count on prescaler ticks rather than on CC0 matches
+            .cnttick().clear_bit()
+            // normal (binary) rather than calendar (BCD) counting
+            .cntmode().clear_bit()
+            .enable().set_bit()
+            );
+
+        Rtc { register, overflows: 0, epoch_offset: None, alarm: None }
+    }
+
+    /// Enable the RTCC interrupt on counter overflows (it still needs to be unmasked in the NVIC).
+    pub fn listen_overflow(&mut self)
+    {
+        self.register.ien.modify(|_, w| w.of().set_bit());
+    }
+
+    /// Number of ticks (at ``TICK_HZ``) since the RTCC was started
+    pub fn uptime_ticks(&mut self) -> u64
+    {
+        cortex_m::interrupt::free(|_| {
+            loop {
+                if self.register.if_.read().of().bit_is_set() {
+                    self.register.ifc.write(|w| w.of().set_bit());
+                    self.overflows += 1;
+                }
+                let low = self.register.cnt.read().cnt().bits();
+                // If the flag is still clear, the counter can not have wrapped between the
+                // overflow check and the read.
+                if self.register.if_.read().of().bit_is_clear() {
+                    return (u64::from(self.overflows) << 32) | u64::from(low);
+                }
+            }
+        })
+    }
+
+    /// Milliseconds since the RTCC was started
+    pub fn uptime_ms(&mut self) -> u64
+    {
+        self.uptime_ticks() * 1000 / u64::from(TICK_HZ)
+    }
+
+    /// A wrapping millisecond timestamp, eg. for marking sensor samples
+    ///
+    /// This wraps around after about 49 days; differences between timestamps should be
+    /// calculated using ``wrapping_sub``.
+    pub fn timestamp_ms(&mut self) -> u32
+    {
+        self.uptime_ms() as u32
+    }
+
+    /// Set the wall clock time, typically from a value the host provides.
+    pub fn set_unix_time(&mut self, seconds: u64)
+    {
+        let now = self.uptime_ticks();
+        self.epoch_offset = Some(now.wrapping_sub(seconds * u64::from(TICK_HZ)));
+    }
+
+    /// Seconds since the Unix epoch, if the wall clock was set
+    pub fn unix_time(&mut self) -> Option<u64>
+    {
+        let offset = self.epoch_offset?;
+        Some(self.uptime_ticks().wrapping_sub(offset) / u64::from(TICK_HZ))
+    }
+
+    /// Current calendar time, if the wall clock was set
+    pub fn now(&mut self) -> Option<DateTime>
+    {
+        self.unix_time().map(DateTime::from_unix)
+    }
+
+    /// Arm the alarm for a given calendar time.
+    ///
+    /// When the alarm is due, the RTCC interrupt fires (once unmasked in the NVIC); its handler
+    /// should then check ``alarm_pending``. Only one alarm can be set at a time, setting another
+    /// replaces the previous one.
+    pub fn set_alarm(&mut self, at: &DateTime) -> Result<(), AlarmError>
+    {
+        let offset = self.epoch_offset.ok_or(AlarmError::NoWallClock)?;
+        let due = offset.wrapping_add(at.to_unix() * u64::from(TICK_HZ));
+        if due <= self.uptime_ticks() {
+            return Err(AlarmError::InPast);
+        }
+        self.arm(due);
+        Ok(())
+    }
+
+    /// Arm the alarm to go off after the given time.
+    pub fn set_alarm_in<T>(&mut self, delay: T)
+        where T: Into<Seconds>
+    {
+        let due = self.uptime_ticks() + u64::from(delay.into().0) * u64::from(TICK_HZ);
+        self.arm(due);
+    }
+
+    fn arm(&mut self, due: u64)
+    {
+        self.alarm = Some(due);
+        // Clearing a stale match before setting the compare value can't lose the new one
+        self.register.ifc.write(|w| w.cc1().set_bit());
+        self.register.cc1_ccv.write(|w| unsafe { w.ccv().bits(due as u32) });
+        self.register.ien.modify(|_, w| w.cc1().set_bit());
+        // A due time that passed already (or while setting up) produces no match any more
+        if due <= self.uptime_ticks() {
+            self.register.ifs.write(|w| w.cc1().set_bit());
+        }
+    }
+
+    /// Disarm the alarm.
+    pub fn clear_alarm(&mut self)
+    {
+        self.alarm = None;
+        self.register.ien.modify(|_, w| w.cc1().clear_bit());
+        self.register.ifc.write(|w| w.cc1().set_bit());
+    }
+
+    /// Check whether the alarm went off, and disarm it if it did.
+    ///
+    /// As the compare channel only sees the lower 32 bits of the tick counter, alarms more than
+    /// 48 days ahead make the interrupt fire early; this takes care of telling those apart and
+    /// leaves the alarm armed for the next round.
+    pub fn alarm_pending(&mut self) -> bool
+    {
+        let due = match self.alarm {
+            Some(due) => due,
+            None => return false,
+        };
+        self.register.ifc.write(|w| w.cc1().set_bit());
+        if self.uptime_ticks() >= due {
+            self.clear_alarm();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Set up compare channel 0 for the asynchronous time driver, and enable the interrupts it
+    /// needs.
+    pub(crate) fn enable_wakeup(&mut self)
+    {
+        self.register.cc0_ctrl.write(|w| w.mode().outputcompare());
+        self.register.ifc.write(|w| w.cc0().set_bit());
+        self.register.ien.modify(|_, w| w.of().set_bit().cc0().set_bit());
+    }
+
+    /// Make the RTCC interrupt fire when the tick counter reaches ``due`` (or, if that is more
+    /// than a counter wrap ahead, earlier).
+    pub(crate) fn set_wakeup(&mut self, due: u64)
+    {
+        self.register.cc0_ccv.write(|w| unsafe { w.ccv().bits(due as u32) });
+    }
+
+    pub(crate) fn clear_wakeup_flag(&mut self)
+    {
+        self.register.ifc.write(|w| w.cc0().set_bit());
+    }
+
+    fn retention(&self) -> *mut u32
+    {
+        // The 32 retention registers are laid out consecutively, but the PAC has them as
+        // individual fields.
+        &self.register.ret0_reg as *const _ as *mut u32
+    }
+
+    /// Read one of the retention registers (``index < RETAINED_WORDS``).
+    pub(crate) fn retained(&self, index: usize) -> u32
+    {
+        assert!(index < RETAINED_WORDS);
+        // unsafe: In bounds as asserted, and the registers are plain read/write memory.
+        unsafe { core::ptr::read_volatile(self.retention().add(index)) }
+    }
+
+    /// Write one of the retention registers (``index < RETAINED_WORDS``).
+    pub(crate) fn set_retained(&mut self, index: usize, value: u32)
+    {
+        assert!(index < RETAINED_WORDS);
+        // unsafe: In bounds as asserted, and the registers are plain read/write memory.
+        unsafe { core::ptr::write_volatile(self.retention().add(index), value) }
+    }
+
+    /// Prepare for EM4: Only keep the alarm interrupt (if armed) enabled, and let it wake the
+    /// device.
+    pub(crate) fn prepare_em4(&mut self)
+    {
+        let alarm = self.alarm.is_some();
+        self.register.ien.write(|w| w.cc1().bit(alarm));
+        self.register.ifc.write(|w| unsafe { w.bits(0xffff_ffff) });
+        self.register.em4wuen.write(|w| w.em4wu().bit(alarm));
+    }
+}
+
+/// The RTCC as RTIC monotonic timer, ticking at ``TICK_HZ``
+///
+/// This uses compare channel 2 (leaving channel 1 to the alarms) and the overflow interrupt, both
+/// on the RTCC interrupt line, which the RTIC application needs to bind the monotonic to. The
+/// counter is not restarted on ``reset``, so instants keep matching ``uptime_ticks``.
+#[cfg(feature = "rtic")]
+impl rtic_monotonic::Monotonic for Rtc {
+    type Instant = fugit::TimerInstantU64<TICK_HZ>;
+    type Duration = fugit::TimerDurationU64<TICK_HZ>;
+
+    fn now(&mut self) -> Self::Instant
+    {
+        Self::Instant::from_ticks(self.uptime_ticks())
+    }
+
+    fn set_compare(&mut self, instant: Self::Instant)
+    {
+        // Instants further ahead than a counter wrap make the interrupt fire early, but RTIC
+        // checks the time when it fires and sets the compare value again.
+        self.register.cc2_ccv.write(|w| unsafe { w.ccv().bits(instant.ticks() as u32) });
+    }
+
+    fn clear_compare_flag(&mut self)
+    {
+        self.register.ifc.write(|w| w.cc2().set_bit());
+    }
+
+    fn zero() -> Self::Instant
+    {
+        Self::Instant::from_ticks(0)
+    }
+
+    unsafe fn reset(&mut self)
+    {
+        self.register.cc2_ctrl.write(|w| w.mode().outputcompare());
+        self.register.ifc.write(|w| w.cc2().set_bit());
+        self.register.ien.modify(|_, w| w.of().set_bit().cc2().set_bit());
+    }
+
+    fn on_interrupt(&mut self)
+    {
+        // Accounts for any pending overflow
+        self.uptime_ticks();
+        // The calendar alarm shares the RTCC interrupt, whose handler RTIC owns; nobody else would
+        // clear its flag, and the interrupt would keep firing. ``alarm_pending`` goes by the time,
+        // not by the flag, so the alarm is still reported.
+        self.register.ifc.write(|w| w.cc1().set_bit());
+    }
+
+    fn enable_timer(&mut self)
+    {
+        self.register.ien.modify(|_, w| w.cc2().set_bit());
+    }
+
+    fn disable_timer(&mut self)
+    {
+        self.register.ien.modify(|_, w| w.cc2().clear_bit());
+    }
+}
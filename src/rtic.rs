@@ -0,0 +1,125 @@
+//! Support for writing board firmware with [RTIC]
+//!
+//! RTIC takes the core peripherals itself and hands them to the ``init`` task, so the regular
+//! ``Board::new()`` (which takes them too) can't be used there. Instead, ``Resources::new`` takes
+//! the device peripherals that RTIC passes to ``init``, and builds the board parts from them in a
+//! shape that can be returned as resources:
+//!
+//! * All parts are ``Send``, so they can be moved into tasks running at any priority -- in
+//!   particular, the PIC and the buttons can be late resources of interrupt tasks.
+//! * Instead of the SysTick delay, which would need the core peripherals and is shared between
+//!   users, the PIC and the application get a ``CycleDelay`` each.
+//! * The RTCC based ``Rtc`` implements ``rtic_monotonic::Monotonic`` and can be used as the
+//!   application's monotonic, bound to the RTCC interrupt. An alarm armed before handing the
+//!   ``Rtc`` over still wakes the chip, but its flag is cleared by the monotonic; it can't be
+//!   serviced from the RTCC handler.
+//!
+//! ```ignore
+//! #[rtic::app(device = efr32xg1, dispatchers = [TIMER0])]
+//! mod app {
+//!     use thunderboard_sltb001a::{rtc::Rtc, rtic::Resources};
+//!
+//!     #[monotonic(binds = RTCC, default = true)]
+//!     type Mono = Rtc;
+//!
+//!     #[init]
+//!     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+//!         let board = Resources::new(cx.device);
+//!         ...
+//!         (Shared { .. }, Local { pic: board.pic, .. }, init::Monotonics(board.mono))
+//!     }
+//! }
+//! ```
+//!
+//! This module is only available with the ``rtic`` feature.
+//!
+//! [RTIC]: https://rtic.rs/
+
+use efm32gg_hal::{
+    gpio::GPIOExt,
+    cmu::CMUExt,
+    timer::TimerExt,
+};
+
+use crate::{battery, button, delay, hibernate, i2c_bus, imu, pic, rtc, timer};
+#[cfg(not(feature = "led-pwm"))]
+use crate::led;
+#[cfg(feature = "led-pwm")]
+use crate::led_pwm;
+
+/// The board's parts, prepared for use as RTIC resources
+///
+/// Like the ``Board``, this is expected to grow, so don't destructure it but just pick its parts.
+pub struct Resources {
+    #[cfg(not(feature = "led-pwm"))]
+    pub leds: led::LEDs,
+    #[cfg(feature = "led-pwm")]
+    pub leds: led_pwm::LEDs,
+    pub buttons: button::Buttons,
+    pub delay: delay::CycleDelay,
+    pub pic: pic::PIC<delay::CycleDelay, i2c_bus::I2cProxy<'static>>,
+    /// The I2C bus shared by the PIC and the sensors
+    pub i2c_bus: &'static i2c_bus::I2cBus,
+    /// The inertial sensor; it needs to be powered through ``pic.set_imu(true)`` to be usable.
+    pub imu: imu::Imu,
+    /// Supply voltage measurement, using ADC0
+    pub battery: battery::BatteryMonitor,
+
+    pub timer1: timer::Timer1,
+    pub letimer0: timer::LETimer0,
+
+    /// The RTCC, to be used as the application's monotonic
+    pub mono: rtc::Rtc,
+
+    /// Whether this start continues an EM4 hibernation, and the state stored for it
+    pub boot: hibernate::Boot,
+}
+
+// Fail the build if any of the resources stops being Send
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Resources>();
+};
+
+impl Resources {
+    /// Build the board parts from the device peripherals RTIC passes to ``init``.
+    pub fn new(p: efr32xg1::Peripherals) -> Self
+    {
+        let cmu = p.CMU.constrain().split();
+
+        let gpios = p.GPIO.split(cmu.gpio);
+
+        #[cfg(feature = "led-pwm")]
+        let leds = led_pwm::LEDs::new(gpios.pd11, gpios.pd12, p.TIMER0.with_clock(cmu.timer0));
+        #[cfg(not(feature = "led-pwm"))]
+        let leds = led::LEDs::new(gpios.pd11, gpios.pd12);
+
+        let buttons = button::Buttons::new(gpios.pd14, gpios.pd15);
+
+        let i2c = i2c_bus::configure(p.I2C0, cmu.i2c0, gpios.pc11, gpios.pc10);
+        let i2c_bus = &*cortex_m::singleton!(: i2c_bus::I2cBus = i2c_bus::I2cBus::new(i2c)).unwrap();
+
+        let mut pic = pic::PIC::with_i2c(i2c_bus.acquire(), delay::CycleDelay::new(), gpios.pd10);
+        crate::reset_pic(&mut pic);
+
+        let mut mono = rtc::Rtc::new(p.RTCC);
+        let boot = hibernate::boot(&mut mono);
+
+        Resources {
+            leds,
+            buttons,
+            delay: delay::CycleDelay::new(),
+            pic,
+            i2c_bus,
+            imu: imu::Imu::new(gpios.pc0, gpios.pc1, gpios.pc2, gpios.pc3),
+            battery: battery::BatteryMonitor::new(p.ADC0),
+
+            timer1: timer::Timer1::new(p.TIMER1.with_clock(cmu.timer1)),
+            letimer0: timer::LETimer0::new(p.LETIMER0),
+
+            mono,
+
+            boot,
+        }
+    }
+}
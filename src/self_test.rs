@@ -0,0 +1,171 @@
+//! Checking that the board's components are present and responsive
+//!
+//! ``Board::self_test`` powers each PIC domain in turn, checks that the expected devices answer
+//! with their IDs, and switches the domain off again. The LEDs are checked for shorts, and the
+//! buttons for being stuck. The result is a ``Report`` that can be inspected component by
+//! component, or printed:
+//!
+//! ```ignore
+//! let report = board.self_test();
+//! writeln!(hio::hstdout().unwrap(), "{}", report).unwrap();
+//! assert!(report.passed());
+//! ```
+//!
+//! Note that a button that is held down during the test is reported as stuck.
+
+use core::fmt;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::button::Buttons;
+use crate::i2c_bus::{I2cBus, I2cProxy};
+use crate::imu::{self, Imu};
+use crate::pic::PIC;
+use crate::probe::{identify, Device};
+
+/// Time the devices take to become responsive after their domain is powered (the Si7021's 80ms,
+/// rounded up; the ICM-20648 and CCS811 are faster)
+const POWER_UP_MS: u16 = 100;
+
+const PIC_ID: [u8; 4] = [0x49, 0x4f, 0x58, 0x50];
+
+/// Outcome of the test of a single component
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Pass,
+    Fail,
+    /// The component can not be tested in this configuration
+    Skipped,
+}
+
+impl From<bool> for Status {
+    fn from(ok: bool) -> Self
+    {
+        if ok { Status::Pass } else { Status::Fail }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        f.write_str(match self {
+            Status::Pass => "pass",
+            Status::Fail => "FAIL",
+            Status::Skipped => "skipped",
+        })
+    }
+}
+
+/// Results of a board self test
+#[derive(Clone, Copy, Debug)]
+pub struct Report {
+    /// The PIC responded with its device ID
+    pub pic: Status,
+    /// Version of the PIC firmware, if the PIC responded
+    pub pic_firmware: Option<[u8; 3]>,
+    /// The ICM-20648 responded on SPI
+    pub imu: Status,
+    pub si7021: Status,
+    pub si1133: Status,
+    pub bmp280: Status,
+    pub ccs811: Status,
+    /// The LED lines are not shorted
+    pub leds: Status,
+    /// Neither button is held down
+    pub buttons: Status,
+}
+
+impl Report {
+    /// True if no component failed
+    pub fn passed(&self) -> bool
+    {
+        self.components().iter().all(|(_, s)| *s != Status::Fail)
+    }
+
+    /// All components with their names, eg. for sending to a host in a custom format
+    pub fn components(&self) -> [(&'static str, Status); 8]
+    {
+        [
+            ("pic", self.pic),
+            ("imu", self.imu),
+            ("si7021", self.si7021),
+            ("si1133", self.si1133),
+            ("bmp280", self.bmp280),
+            ("ccs811", self.ccs811),
+            ("leds", self.leds),
+            ("buttons", self.buttons),
+        ]
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        for (name, status) in self.components().iter() {
+            write!(f, "{}: {}", name, status)?;
+            if let (&"pic", Some([major, minor, patch])) = (name, self.pic_firmware) {
+                write!(f, " (firmware {}.{}.{})", major, minor, patch)?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "overall: {}", Status::from(self.passed()))
+    }
+}
+
+/// Check the I2C devices of a domain that was just powered.
+fn check_i2c(i2c: &mut I2cProxy<'_>, addresses: &[u8], device: Device) -> Status
+{
+    Status::from(addresses.iter().any(|a| identify(i2c, *a) == device))
+}
+
+/// Run the checks on the individual parts; see ``Board::self_test``.
+///
+/// The LEDs are passed in only as a check result, as their type depends on the crate features.
+pub(crate) fn run<D, DM>(pic: &mut PIC<D, I2cProxy<'_>>, bus: &I2cBus, delay: &mut DM, imu: &mut Imu, buttons: &Buttons, leds: Status) -> Report
+    where D: DelayUs<u16>,
+          DM: DelayMs<u16>,
+{
+    let mut i2c = bus.acquire();
+
+    let pic_ok = pic.read_device_id().ok() == Some(PIC_ID);
+    let pic_firmware = if pic_ok { pic.read_firmware_version().ok() } else { None };
+
+    // Without a PIC, none of the domains can be powered. A domain that fails to switch on shows
+    // in the check of its devices.
+    let (imu_status, si7021, si1133, bmp280, ccs811) = if pic_ok {
+        let _ = pic.set_imu(true);
+        delay.delay_ms(POWER_UP_MS);
+        let imu_status = Status::from(imu.who_am_i() == imu::WHO_AM_I);
+        let _ = pic.set_imu(false);
+
+        let _ = pic.set_env_sensor(true);
+        delay.delay_ms(POWER_UP_MS);
+        let si7021 = check_i2c(&mut i2c, &[0x40], Device::Si7021);
+        let si1133 = check_i2c(&mut i2c, &[0x55], Device::Si1133);
+        let bmp280 = check_i2c(&mut i2c, &[0x76, 0x77], Device::Bmp280);
+        let _ = pic.set_env_sensor(false);
+
+        let _ = pic.set_ccs(true, true);
+        delay.delay_ms(POWER_UP_MS);
+        let ccs811 = check_i2c(&mut i2c, &[0x5a, 0x5b], Device::Ccs811);
+        let _ = pic.set_ccs(false, false);
+
+        (imu_status, si7021, si1133, bmp280, ccs811)
+    } else {
+        (Status::Skipped, Status::Skipped, Status::Skipped, Status::Skipped, Status::Skipped)
+    };
+
+    let buttons = Status::from(!buttons.button0_pressed() && !buttons.button1_pressed());
+
+    Report {
+        pic: Status::from(pic_ok),
+        pic_firmware,
+        imu: imu_status,
+        si7021,
+        si1133,
+        bmp280,
+        ccs811,
+        leds,
+        buttons,
+    }
+}
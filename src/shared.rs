@@ -0,0 +1,92 @@
+//! Handing owned board parts over to interrupt handlers
+//!
+//! Interrupt handlers can't take arguments, so anything they work on needs to be reachable from a
+//! static. An ``InterruptShared`` is a slot that can live in a (non-mut) static, be filled from the
+//! main function once the board is set up, and then be used from the handler -- without any
+//! ``unsafe`` in the application and without needing nightly features, as it can be built in a
+//! const context.
+//!
+//! There are two ways of using it from the handler:
+//!
+//! * ``take`` moves the value out into the handler, typically into a handler-local ``static mut``
+//!   (which cortex-m-rt makes safely accessible). This is the way to go if only the handler uses
+//!   the value after the handover, as the handler then runs without holding a critical section.
+//!
+//! * ``with`` gives access to the value for the duration of a closure, from wherever it is called.
+//!   This is the way to go if the main loop and the handler both need the value, but note that
+//!   the closure runs in a critical section, so it should be short.
+//!
+//! ```ignore
+//! static FOR_TIMER1: InterruptShared<(Buttons, LEDs)> = InterruptShared::new();
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!     let board = thunderboard_sltb001a::Board::new();
+//!     FOR_TIMER1.put((board.buttons, board.leds));
+//!     ...
+//! }
+//!
+//! #[interrupt]
+//! fn TIMER1() {
+//!     static mut STUFF: Option<(Buttons, LEDs)> = None;
+//!     if STUFF.is_none() {
+//!         *STUFF = FOR_TIMER1.take();
+//!     }
+//!     ...
+//! }
+//! ```
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{self, Mutex};
+
+/// A slot through which a value can be handed to (or shared with) interrupt handlers
+pub struct InterruptShared<T> {
+    inner: Mutex<RefCell<Option<T>>>,
+}
+
+impl<T> InterruptShared<T> {
+    /// Create an empty slot; being const, this can be used to initialize a static.
+    pub const fn new() -> Self
+    {
+        InterruptShared { inner: Mutex::new(RefCell::new(None)) }
+    }
+
+    /// Place a value in the slot.
+    ///
+    /// If the slot was occupied already, the new value is handed back as an error.
+    pub fn put(&self, value: T) -> Result<(), T>
+    {
+        interrupt::free(|cs| {
+            let mut slot = self.inner.borrow(cs).borrow_mut();
+            match *slot {
+                Some(_) => Err(value),
+                None => {
+                    *slot = Some(value);
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    /// Move the value out of the slot, if there is one.
+    pub fn take(&self) -> Option<T>
+    {
+        interrupt::free(|cs| self.inner.borrow(cs).borrow_mut().take())
+    }
+
+    /// Run a closure on the value in the slot (if there is one) inside a critical section.
+    ///
+    /// Calls to ``with`` must not be nested on the same slot, or this panics.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R>
+    {
+        interrupt::free(|cs| self.inner.borrow(cs).borrow_mut().as_mut().map(f))
+    }
+}
+
+impl<T> Default for InterruptShared<T> {
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
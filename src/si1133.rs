@@ -0,0 +1,850 @@
+//! Driver for the Si1133 UV index and ambient light sensor
+//!
+//! The sensor sits at address 0x55 of the board's I2C bus, and is powered with the environmental
+//! sensor group (``PIC::set_env_sensor``), after which it needs 25ms to start up.
+//!
+//! The Si1133 has up to six channels, each of which measures one of its photodiodes (see
+//! ``Photodiode``) with its own gain settings. Those settings live in a parameter table inside the
+//! sensor, which is only accessible through a command interface; ``configure`` sets up the
+//! channels. ``STANDARD_CHANNELS`` is the setup of Silicon Labs' reference driver for this board:
+//! UV, two visible light ranges and infrared.
+//!
+//! Measurements are either triggered by the host (forced mode, ``measure_all`` or
+//! ``start_forced``/``read_samples``), or run periodically by the sensor itself (autonomous mode,
+//! ``start_autonomous``), in which case new samples are signalled through the PIC's ``uv``
+//! interrupt.
+//!
+//! Results are raw ADC counts. With the standard channels, ``measure_light`` turns them into lux and
+//! UV index using Silicon Labs' published calibration polynomials, and adjusts each channel's
+//! integration time so that the measurement neither saturates in direct sunlight nor drowns in
+//! noise in a dark room.
+//!
+//! For waking up on changes in the light, the sensor can watch a window of illuminance or UV index
+//! values on its own (``start_window``), and raise its interrupt when the light leaves it. That
+//! interrupt arrives at the PIC as its ``uv`` source, which ``enable_pic_interrupt`` enables and
+//! ``handle_pic_interrupt`` services:
+//!
+//! ```ignore
+//! light.start_window(Window { quantity: Quantity::Lux, lower: 50_000, upper: 200_000 }, 500)?;
+//! light.enable_pic_interrupt(&mut pic, InterruptSet::default())?;
+//! loop {
+//!     let pending = pic.wait_for_interrupt().await?;
+//!     match light.handle_pic_interrupt(&mut pic, pending)? {
+//!         Some(Crossing::Above) => lights_off(),
+//!         Some(Crossing::Below) => lights_on(),
+//!         None => (),
+//!     }
+//! }
+//! ```
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+use crate::i2c_bus::{self, I2cProxy};
+use crate::pic::{InterruptConfiguration, InterruptSet, PIC};
+
+pub const ADDR: u8 = 0x55;
+/// Content of the PART_ID register
+pub const PART_ID: u8 = 0x33;
+
+const REG_PART_ID: u8 = 0x00;
+const REG_HOSTIN0: u8 = 0x0a;
+const REG_COMMAND: u8 = 0x0b;
+const REG_IRQ_ENABLE: u8 = 0x0f;
+const REG_RESPONSE1: u8 = 0x10;
+const REG_RESPONSE0: u8 = 0x11;
+const REG_IRQ_STATUS: u8 = 0x12;
+const REG_HOSTOUT0: u8 = 0x13;
+
+const CMD_RESET_CMD_CTR: u8 = 0x00;
+const CMD_RESET_SW: u8 = 0x01;
+const CMD_FORCE: u8 = 0x11;
+const CMD_PAUSE: u8 = 0x12;
+const CMD_START: u8 = 0x13;
+const CMD_PARAM_QUERY: u8 = 0x40;
+const CMD_PARAM_SET: u8 = 0x80;
+
+const RESPONSE0_CMD_CTR: u8 = 0x0f;
+const RESPONSE0_CMD_ERR: u8 = 0x10;
+
+const PARAM_CHAN_LIST: u8 = 0x01;
+/// ADCCONFIG of channel 0; the ADCSENS, ADCPOST and MEASCONFIG parameters follow, and the next
+/// channel's parameters start 4 addresses later.
+const PARAM_ADCCONFIG0: u8 = 0x02;
+const PARAM_MEASRATE_H: u8 = 0x1a;
+const PARAM_MEASCOUNT0: u8 = 0x1c;
+const PARAM_UPPER_THRESHOLD_H: u8 = 0x29;
+const PARAM_LOWER_THRESHOLD_H: u8 = 0x2c;
+
+/// THRESH_SEL setting of ADCPOST that interrupts when the result is outside the window given by
+/// the UPPER_THRESHOLD and LOWER_THRESHOLD parameters
+const ADCPOST_THRESH_WINDOW: u8 = 0x03;
+
+/// Error codes reported in RESPONSE0 (with CMD_ERR set) when a measurement overflows
+pub const ERR_ADC_OVERFLOW: u8 = 0x12;
+pub const ERR_ACCUMULATION_OVERFLOW: u8 = 0x13;
+
+/// MEASCONFIG counter index that makes a channel use MEASCOUNT0 in autonomous mode
+const MEASCONFIG_COUNTER0: u8 = 0x40;
+
+/// Unit of the MEASRATE parameter, in microseconds
+const MEASRATE_UNIT_US: u32 = 800;
+
+pub const MAX_CHANNELS: usize = 6;
+
+/// How often a blocking measurement polls for its result (at 1ms intervals) before giving up
+const POLL_LIMIT: u8 = 250;
+
+#[derive(Debug)]
+pub enum Error {
+    I2c(i2c_bus::Error),
+    /// The sensor rejected a command or a measurement overflowed, with the error code it reported
+    /// (0x10 to 0x13, see the ``RESPONSE0`` register)
+    Command(u8),
+    /// The sensor did not respond to a command, or a measurement did not complete in time
+    Timeout,
+}
+
+impl From<i2c_bus::Error> for Error {
+    fn from(e: i2c_bus::Error) -> Self
+    {
+        Error::I2c(e)
+    }
+}
+
+/// Photodiode (ADCMUX setting) a channel measures
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Photodiode {
+    SmallIr = 0x00,
+    MediumIr = 0x01,
+    LargeIr = 0x02,
+    White = 0x0b,
+    LargeWhite = 0x0d,
+    Uv = 0x18,
+    UvDeep = 0x19,
+}
+
+/// Configuration of a measurement channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelConfig {
+    pub photodiode: Photodiode,
+    /// ADC decimation rate, 0 to 3 (with 0 being the slowest and most precise)
+    pub decimation: u8,
+    /// Integration time, as a power of two of 24.4µs, 0 to 11
+    pub hw_gain: u8,
+    /// Number of measurements that are accumulated, as a power of two, 0 to 7
+    pub sw_gain: u8,
+    /// Reduce the sensitivity by about 14.5 for bright light
+    pub high_signal: bool,
+    /// Number of bits the accumulated result is shifted right by, 0 to 7
+    pub post_shift: u8,
+    /// Produce signed 24-bit rather than unsigned 16-bit results
+    pub output_24bit: bool,
+}
+
+impl ChannelConfig {
+    fn adcconfig(&self) -> u8
+    {
+        ((self.decimation & 0x03) << 5) | self.photodiode as u8
+    }
+
+    fn adcsens(&self) -> u8
+    {
+        ((self.high_signal as u8) << 7) | ((self.sw_gain & 0x07) << 4) | (self.hw_gain & 0x0f)
+    }
+
+    fn adcpost(&self) -> u8
+    {
+        ((self.output_24bit as u8) << 6) | ((self.post_shift & 0x07) << 3)
+    }
+
+    fn output_len(&self) -> usize
+    {
+        if self.output_24bit { 3 } else { 2 }
+    }
+
+    /// Overall gain (apart from the photodiode and high signal range), as a power of two
+    fn gain_log2(&self) -> i32
+    {
+        i32::from(self.hw_gain) + i32::from(self.sw_gain) - i32::from(self.post_shift)
+    }
+
+    /// Convert counts measured with this configuration to what ``other`` would have measured,
+    /// assuming the same photodiode and range.
+    fn rescale(&self, value: i64, other: &ChannelConfig) -> i64
+    {
+        let shift = other.gain_log2() - self.gain_log2();
+        if shift >= 0 { value << shift } else { value >> -shift }
+    }
+}
+
+/// Index of the UV channel in ``STANDARD_CHANNELS``
+pub const UV: usize = 0;
+/// Index of the visible light channel for bright light in ``STANDARD_CHANNELS``
+pub const VISIBLE_HIGH: usize = 1;
+/// Index of the infrared channel in ``STANDARD_CHANNELS``
+pub const IR: usize = 2;
+/// Index of the visible light channel for dim light in ``STANDARD_CHANNELS``
+pub const VISIBLE_LOW: usize = 3;
+
+/// The channel setup of Silicon Labs' reference driver for the board
+pub const STANDARD_CHANNELS: [ChannelConfig; 4] = [
+    ChannelConfig { photodiode: Photodiode::Uv, decimation: 3, hw_gain: 1, sw_gain: 7, high_signal: false, post_shift: 0, output_24bit: true },
+    ChannelConfig { photodiode: Photodiode::LargeWhite, decimation: 2, hw_gain: 1, sw_gain: 6, high_signal: true, post_shift: 0, output_24bit: true },
+    ChannelConfig { photodiode: Photodiode::MediumIr, decimation: 2, hw_gain: 1, sw_gain: 6, high_signal: true, post_shift: 2, output_24bit: true },
+    ChannelConfig { photodiode: Photodiode::LargeWhite, decimation: 2, hw_gain: 7, sw_gain: 0, high_signal: true, post_shift: 0, output_24bit: true },
+];
+
+/// Index of the channel that watches the window set up by ``start_window``, after the standard
+/// channels
+pub const THRESHOLD_CHANNEL: usize = 4;
+
+/// Configuration of the ``THRESHOLD_CHANNEL`` when watching illuminance: That of VISIBLE_LOW, but
+/// with 16-bit output, as the thresholds are 16 bits wide.
+const THRESHOLD_LUX: ChannelConfig = ChannelConfig { photodiode: Photodiode::LargeWhite, decimation: 2, hw_gain: 7, sw_gain: 0, high_signal: true, post_shift: 0, output_24bit: false };
+/// Configuration of the ``THRESHOLD_CHANNEL`` when watching the UV index
+const THRESHOLD_UV: ChannelConfig = ChannelConfig { photodiode: Photodiode::Uv, decimation: 3, hw_gain: 4, sw_gain: 0, high_signal: false, post_shift: 0, output_24bit: false };
+
+/// Results of a measurement of all configured channels, in channel order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Samples {
+    values: [i32; MAX_CHANNELS],
+    count: usize,
+}
+
+impl Samples {
+    pub fn get(&self, channel: usize) -> Option<i32>
+    {
+        self.values[..self.count].get(channel).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &i32>
+    {
+        self.values[..self.count].iter()
+    }
+}
+
+/// A calibration polynomial term as used in Silicon Labs' reference driver: ``info`` packs the
+/// sign (bit 7), the x and y orders (bits 6-4 and 2-0) and the shift applied to each factor (the
+/// ones' complement of the upper byte); ``mag`` is the divisor.
+#[derive(Clone, Copy)]
+struct Coefficient {
+    info: i16,
+    mag: u16,
+}
+
+const fn c(info: i16, mag: u16) -> Coefficient
+{
+    Coefficient { info, mag }
+}
+
+/// Lux polynomial for bright light, on the VISIBLE_HIGH and IR channels
+const LUX_HIGH: [Coefficient; 4] = [c(0, 209), c(1665, 93), c(2064, 65), c(-2671, 234)];
+/// Lux polynomial for dim light, on the VISIBLE_LOW and IR channels
+const LUX_LOW: [Coefficient; 9] = [
+    c(0, 0), c(1921, 29053), c(-1022, 36363), c(2320, 20789), c(-367, 57909),
+    c(-1774, 38240), c(-608, 46775), c(-1503, 51831), c(-1886, 58928),
+];
+/// UV index polynomial, on the UV channel
+const UV_INDEX: [Coefficient; 2] = [c(1281, 30902), c(-638, 46301)];
+
+/// Counts above which the bright light polynomial is used
+const LUX_HIGH_THRESHOLD: i64 = 16_000;
+const LUX_INPUT_FRACTION_HIGH: u32 = 7;
+const LUX_INPUT_FRACTION_LOW: u32 = 15;
+const UV_INPUT_FRACTION: u32 = 15;
+const OUTPUT_FRACTION: u32 = 12;
+
+/// Evaluate a calibration polynomial in x and y, with the result in Q12.
+fn eval_poly(x: i64, y: i64, input_fraction: u32, coefficients: &[Coefficient]) -> i64
+{
+    let mut output = 0;
+    for k in coefficients {
+        let info = k.info as u16;
+        let x_order = (info >> 4) & 0x07;
+        let y_order = info & 0x07;
+        let shift = -(i32::from(!((info >> 8) as u8) as i8) + 1);
+        let sign = if info & 0x80 != 0 { -1 } else { 1 };
+
+        let inner = |value: i64| {
+            let scaled = (value << input_fraction) / i64::from(k.mag);
+            if shift < 0 { scaled >> -shift } else { scaled << shift }
+        };
+        let factor = |value: i64, order: u16| match order {
+            0 => 1,
+            1 => inner(value),
+            _ => inner(value) * inner(value),
+        };
+
+        output += if x_order == 0 && y_order == 0 {
+            sign * (i64::from(k.mag) << OUTPUT_FRACTION)
+        } else {
+            sign * factor(x, x_order) * factor(y, y_order)
+        };
+    }
+    output.abs()
+}
+
+/// Illuminance in millilux, from counts of the standard VISIBLE_HIGH, VISIBLE_LOW and IR channels
+pub fn millilux(visible_high: i64, visible_low: i64, ir: i64) -> u32
+{
+    let q12 = if visible_high > LUX_HIGH_THRESHOLD || ir > LUX_HIGH_THRESHOLD {
+        eval_poly(visible_high, ir, LUX_INPUT_FRACTION_HIGH, &LUX_HIGH)
+    } else {
+        eval_poly(visible_low, ir, LUX_INPUT_FRACTION_LOW, &LUX_LOW)
+    };
+    ((q12 * 1000) >> OUTPUT_FRACTION).min(i64::from(u32::MAX)) as u32
+}
+
+/// UV index in hundredths, from counts of the standard UV channel
+pub fn uv_index(uv: i64) -> u16
+{
+    let q12 = eval_poly(0, uv, UV_INPUT_FRACTION, &UV_INDEX);
+    ((q12 * 100) >> OUTPUT_FRACTION).min(i64::from(u16::MAX)) as u16
+}
+
+/// Light measurement derived from the standard channels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    /// Illuminance in millilux
+    pub millilux: u32,
+    /// UV index in hundredths
+    pub uv_index: u16,
+}
+
+impl Light {
+    /// Compute the light values from samples of the ``STANDARD_CHANNELS``.
+    pub fn from_samples(samples: &Samples) -> Self
+    {
+        let get = |channel| i64::from(samples.get(channel).expect("Samples are not from the standard channels"));
+        Light {
+            millilux: millilux(get(VISIBLE_HIGH), get(VISIBLE_LOW), get(IR)),
+            uv_index: uv_index(get(UV)),
+        }
+    }
+}
+
+/// Quantity a threshold window applies to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quantity {
+    /// Illuminance, with the window bounds in millilux
+    Lux,
+    /// UV index, with the window bounds in hundredths
+    UvIndex,
+}
+
+/// Range of light values outside of which ``check_crossing`` reports a crossing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Window {
+    pub quantity: Quantity,
+    pub lower: u32,
+    pub upper: u32,
+}
+
+/// Direction in which the light left a window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Crossing {
+    Above,
+    Below,
+}
+
+/// Window bounds in counts of the ``THRESHOLD_CHANNEL``
+#[derive(Clone, Copy, Debug)]
+struct ArmedWindow {
+    lower: u16,
+    upper: u16,
+}
+
+/// Infrared share of the visible light (as counts of the standard IR and VISIBLE_LOW channels, in
+/// per-mille) assumed for lux windows until ``measure_light`` has seen the actual light
+const DEFAULT_IR_SHARE: i64 = 200;
+
+/// How many measurements ``measure_light`` takes at most while adjusting the ranges
+const RANGING_ATTEMPTS: u8 = 4;
+/// How far ``measure_light`` may raise a channel's integration time over the standard one (as a
+/// power of two), limiting a measurement in the dark to about 100ms
+const MAX_GAIN_INCREASE: u8 = 4;
+/// Full scale of a single (not accumulated) measurement, which is a signed 16-bit value for any
+/// decimation and ``hw_gain``
+const ADC_FULL_SCALE: i64 = 0x7fff;
+/// Counts per single measurement above which a channel's integration time is shortened, and below
+/// which it is extended
+///
+/// Each ``hw_gain`` step doubles or halves the counts. Staying below half the full scale leaves
+/// room for the light to double between two measurements; below 1/128 of it, the few counts of
+/// ADC noise exceed a percent. The factor between both is large enough that a channel adjusted by
+/// one step does not bounce back. Light that changes faster than that overflows, which
+/// ``measure_light`` catches as an error and handles by shortening all channels.
+const RANGE_HIGH_COUNTS: i64 = ADC_FULL_SCALE / 2;
+const RANGE_LOW_COUNTS: i64 = ADC_FULL_SCALE / 128;
+
+pub struct Si1133<'a> {
+    i2c: I2cProxy<'a>,
+    channels: [ChannelConfig; MAX_CHANNELS],
+    count: usize,
+    /// Infrared share of the light at the last ``measure_light``, for converting lux windows
+    ir_share: i64,
+    window: Option<ArmedWindow>,
+    /// The threshold channel's interrupt flag, if it was seen (and thus cleared) while waiting
+    /// for other measurements
+    threshold_pending: bool,
+    /// Interrupt flags seen since the last complete set of samples; reading IRQ_STATUS clears it,
+    /// and the channels complete one after another.
+    seen: u8,
+}
+
+impl<'a> Si1133<'a> {
+    /// Create a driver for a sensor that has not been configured yet; see ``init``.
+    pub fn new(i2c: I2cProxy<'a>) -> Self
+    {
+        Si1133 {
+            i2c,
+            channels: [STANDARD_CHANNELS[0]; MAX_CHANNELS],
+            count: 0,
+            ir_share: DEFAULT_IR_SHARE,
+            window: None,
+            threshold_pending: false,
+            seen: 0,
+        }
+    }
+
+    fn read_register(&mut self, register: u8) -> Result<u8, Error>
+    {
+        let mut buf = [0];
+        self.i2c.write_read(ADDR, &[register], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), Error>
+    {
+        Ok(self.i2c.write(ADDR, &[register, value])?)
+    }
+
+    pub fn part_id(&mut self) -> Result<u8, Error>
+    {
+        self.read_register(REG_PART_ID)
+    }
+
+    /// Run a command, with ``input`` going to HOSTIN0, and wait for the sensor to acknowledge it.
+    fn command(&mut self, command: u8, input: u8) -> Result<(), Error>
+    {
+        let before = self.read_register(REG_RESPONSE0)? & RESPONSE0_CMD_CTR;
+        // HOSTIN0 and COMMAND are adjacent, so both can be written at once
+        self.i2c.write(ADDR, &[REG_HOSTIN0, input, command])?;
+
+        for _ in 0..POLL_LIMIT {
+            let response = self.read_register(REG_RESPONSE0)?;
+            self.check_response(response)?;
+            // The counter is reset by this very command, and not incremented
+            if command == CMD_RESET_CMD_CTR || response & RESPONSE0_CMD_CTR != before {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Turn an error flagged in RESPONSE0 into an ``Error::Command``.
+    fn check_response(&mut self, response: u8) -> Result<(), Error>
+    {
+        if response & RESPONSE0_CMD_ERR != 0 {
+            // The error sticks until the counter is reset
+            self.write_register(REG_COMMAND, CMD_RESET_CMD_CTR)?;
+            return Err(Error::Command(response & (RESPONSE0_CMD_ERR | RESPONSE0_CMD_CTR)));
+        }
+        Ok(())
+    }
+
+    pub fn set_parameter(&mut self, address: u8, value: u8) -> Result<(), Error>
+    {
+        self.command(CMD_PARAM_SET | address, value)
+    }
+
+    pub fn parameter(&mut self, address: u8) -> Result<u8, Error>
+    {
+        self.command(CMD_PARAM_QUERY | address, 0)?;
+        self.read_register(REG_RESPONSE1)
+    }
+
+    /// Reset the sensor, wait for it to restart, and configure the ``STANDARD_CHANNELS``.
+    pub fn init(&mut self, delay: &mut impl DelayMs<u8>) -> Result<(), Error>
+    {
+        self.write_register(REG_COMMAND, CMD_RESET_SW)?;
+        delay.delay_ms(10);
+        self.configure(&STANDARD_CHANNELS)
+    }
+
+    /// Set up the channels that are measured, in the given order.
+    ///
+    /// The sensor must not be in autonomous mode.
+    pub fn configure(&mut self, channels: &[ChannelConfig]) -> Result<(), Error>
+    {
+        assert!(channels.len() <= MAX_CHANNELS, "Too many channels");
+
+        for (i, channel) in channels.iter().enumerate() {
+            self.configure_channel(i, channel)?;
+        }
+        self.channels[..channels.len()].copy_from_slice(channels);
+        self.window = None;
+        self.enable_channels(channels.len())
+    }
+
+    /// Measure the first ``count`` channels, with interrupts for all but a threshold channel
+    fn enable_channels(&mut self, count: usize) -> Result<(), Error>
+    {
+        self.count = count;
+        self.set_parameter(PARAM_CHAN_LIST, ((1u16 << count) - 1) as u8)?;
+        let irq = match self.window {
+            Some(_) => 1 << THRESHOLD_CHANNEL,
+            None => self.measurement_mask(),
+        };
+        self.write_register(REG_IRQ_ENABLE, irq)
+    }
+
+    /// Interrupt flags that indicate completed measurements (as opposed to threshold crossings)
+    fn measurement_mask(&self) -> u8
+    {
+        let all = ((1u16 << self.count) - 1) as u8;
+        match self.window {
+            Some(_) => all & !(1 << THRESHOLD_CHANNEL),
+            None => all,
+        }
+    }
+
+    /// Read (and thereby clear) the interrupt flags, keeping note of a threshold crossing
+    fn read_status(&mut self) -> Result<u8, Error>
+    {
+        let status = self.read_register(REG_IRQ_STATUS)?;
+        self.seen |= status;
+        if self.window.is_some() && status & (1 << THRESHOLD_CHANNEL) != 0 {
+            self.threshold_pending = true;
+        }
+        Ok(status)
+    }
+
+    pub(crate) fn configure_channel(&mut self, index: usize, channel: &ChannelConfig) -> Result<(), Error>
+    {
+        let base = PARAM_ADCCONFIG0 + 4 * index as u8;
+        self.set_parameter(base, channel.adcconfig())?;
+        self.set_parameter(base + 1, channel.adcsens())?;
+        self.set_parameter(base + 2, channel.adcpost())?;
+        self.channels[index] = *channel;
+        Ok(())
+    }
+
+    /// The configured channels
+    pub fn channels(&self) -> &[ChannelConfig]
+    {
+        &self.channels[..self.count]
+    }
+
+    /// Trigger a single measurement of all configured channels.
+    pub fn start_forced(&mut self) -> Result<(), Error>
+    {
+        // Clear any stale completion flags
+        self.read_status()?;
+        self.seen = 0;
+        self.command(CMD_FORCE, 0)
+    }
+
+    /// Fetch the results once all channels have completed their measurement.
+    ///
+    /// This works both for forced and autonomous measurements. A measurement that overflowed is
+    /// reported as ``Error::Command`` with ``ERR_ADC_OVERFLOW`` or ``ERR_ACCUMULATION_OVERFLOW``.
+    pub fn read_samples(&mut self) -> nb::Result<Samples, Error>
+    {
+        let mask = self.measurement_mask();
+        // Overflows are only reported after the command that started the measurement succeeded
+        let response = self.read_register(REG_RESPONSE0).map_err(nb::Error::Other)?;
+        self.check_response(response).map_err(nb::Error::Other)?;
+        self.read_status().map_err(nb::Error::Other)?;
+        if self.seen & mask != mask {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.seen &= !mask;
+        self.read_outputs().map_err(nb::Error::Other)
+    }
+
+    fn read_outputs(&mut self) -> Result<Samples, Error>
+    {
+        let mut buf = [0; 3 * MAX_CHANNELS];
+        let len = self.channels().iter().map(ChannelConfig::output_len).sum();
+        self.i2c.write_read(ADDR, &[REG_HOSTOUT0], &mut buf[..len])?;
+
+        let mut samples = Samples { values: [0; MAX_CHANNELS], count: self.count };
+        let mut offset = 0;
+        for (value, channel) in samples.values.iter_mut().zip(self.channels()) {
+            *value = if channel.output_24bit {
+                // Sign extension through the top byte
+                i32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], 0]) >> 8
+            } else {
+                i32::from(u16::from_be_bytes([buf[offset], buf[offset + 1]]))
+            };
+            offset += channel.output_len();
+        }
+        Ok(samples)
+    }
+
+    /// Measure all configured channels once, waiting for the result.
+    pub fn measure_all(&mut self, delay: &mut impl DelayMs<u8>) -> Result<Samples, Error>
+    {
+        self.start_forced()?;
+        for _ in 0..POLL_LIMIT {
+            match self.read_samples() {
+                Err(nb::Error::WouldBlock) => delay.delay_ms(1),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Ok(samples) => return Ok(samples),
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Start measuring all configured channels every ``period_ms`` milliseconds (up to about 52s).
+    ///
+    /// The sensor raises its interrupt line when new samples are available; they are read with
+    /// ``read_samples``.
+    pub fn start_autonomous(&mut self, period_ms: u32) -> Result<(), Error>
+    {
+        let rate = (period_ms.saturating_mul(1000) / MEASRATE_UNIT_US).clamp(1, 0xffff) as u16;
+        let [high, low] = rate.to_be_bytes();
+        self.set_parameter(PARAM_MEASRATE_H, high)?;
+        self.set_parameter(PARAM_MEASRATE_H + 1, low)?;
+        self.set_parameter(PARAM_MEASCOUNT0, 1)?;
+        for i in 0..self.count {
+            self.set_parameter(PARAM_ADCCONFIG0 + 4 * i as u8 + 3, MEASCONFIG_COUNTER0)?;
+        }
+        self.command(CMD_START, 0)
+    }
+
+    /// Stop autonomous measurements.
+    pub fn pause(&mut self) -> Result<(), Error>
+    {
+        self.command(CMD_PAUSE, 0)
+    }
+
+    /// Counts of a channel scaled to what its standard configuration would have measured
+    fn normalized(&self, samples: &Samples, index: usize) -> i64
+    {
+        self.channels[index].rescale(i64::from(samples.get(index).unwrap_or(0)), &STANDARD_CHANNELS[index])
+    }
+
+    /// Change the integration times of the channels whose samples were out of the useful range;
+    /// returns whether any was changed.
+    fn adjust_ranges(&mut self, samples: Option<&Samples>) -> Result<bool, Error>
+    {
+        let mut adjusted = false;
+        for (index, standard) in STANDARD_CHANNELS.iter().enumerate() {
+            let mut channel = self.channels[index];
+            let max_gain = standard.hw_gain + MAX_GAIN_INCREASE;
+            // Without samples, the measurement overflowed in an unknown channel
+            let per_measurement = samples.map(|s| (i64::from(s.get(index).unwrap_or(0)) >> channel.sw_gain) << channel.post_shift);
+            match per_measurement {
+                Some(counts) if counts < RANGE_LOW_COUNTS && channel.hw_gain < max_gain => channel.hw_gain += 1,
+                Some(counts) if counts <= RANGE_HIGH_COUNTS => continue,
+                _ if channel.hw_gain > 0 => channel.hw_gain -= 1,
+                _ => continue,
+            }
+            self.configure_channel(index, &channel)?;
+            adjusted = true;
+        }
+        Ok(adjusted)
+    }
+
+    /// Measure illuminance and UV index, adjusting the integration times to the light level.
+    ///
+    /// This needs the ``STANDARD_CHANNELS`` (as set up by ``init``), whose integration times it
+    /// changes. A sudden change from dark to bright (or back) takes a few measurements to adjust
+    /// to, all of which are done in here; the adjusted ranges are kept for the next call.
+    pub fn measure_light(&mut self, delay: &mut impl DelayMs<u8>) -> Result<Light, Error>
+    {
+        debug_assert!(self.count >= STANDARD_CHANNELS.len() && self.channels().iter().zip(STANDARD_CHANNELS.iter()).all(|(a, b)| a.photodiode == b.photodiode),
+                      "measure_light needs the standard channels");
+
+        let mut last = None;
+        for _ in 0..RANGING_ATTEMPTS {
+            match self.measure_all(delay) {
+                Err(Error::Command(ERR_ADC_OVERFLOW)) | Err(Error::Command(ERR_ACCUMULATION_OVERFLOW)) => {
+                    self.adjust_ranges(None)?;
+                }
+                Err(e) => return Err(e),
+                Ok(samples) => {
+                    let (visible_low, ir) = (self.normalized(&samples, VISIBLE_LOW), self.normalized(&samples, IR));
+                    let light = Light {
+                        millilux: millilux(self.normalized(&samples, VISIBLE_HIGH), visible_low, ir),
+                        uv_index: uv_index(self.normalized(&samples, UV)),
+                    };
+                    if visible_low > 0 {
+                        self.ir_share = ir * 1000 / visible_low;
+                    }
+                    if !self.adjust_ranges(Some(&samples))? {
+                        return Ok(light);
+                    }
+                    // A reading in a better range is coming up, but this one is usable if the
+                    // ranging does not settle
+                    last = Some(light);
+                }
+            }
+        }
+        last.ok_or(Error::Timeout)
+    }
+
+    /// Counts of the threshold channel at which the light reaches ``value``
+    fn threshold_counts(&self, quantity: Quantity, value: u32) -> u16
+    {
+        let ir_share = self.ir_share;
+        // Light values in terms of counts of the standard channel the threshold channel mirrors
+        let light_at = |counts: i64| match quantity {
+            // The standard VISIBLE_HIGH channel has the same overall gain as VISIBLE_LOW
+            Quantity::Lux => millilux(counts, counts, counts * ir_share / 1000),
+            Quantity::UvIndex => u32::from(uv_index(counts)),
+        };
+        // Both are increasing in the counts, so a bisection finds the lowest count reaching value
+        let (mut low, mut high) = (0, 1 << 24);
+        while low < high {
+            let middle = (low + high) / 2;
+            if light_at(middle) < value {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        let (config, standard) = match quantity {
+            Quantity::Lux => (THRESHOLD_LUX, STANDARD_CHANNELS[VISIBLE_LOW]),
+            Quantity::UvIndex => (THRESHOLD_UV, STANDARD_CHANNELS[UV]),
+        };
+        standard.rescale(low, &config).clamp(0, 0xffff) as u16
+    }
+
+    fn set_thresholds(&mut self, window: ArmedWindow) -> Result<(), Error>
+    {
+        let [upper_high, upper_low] = window.upper.to_be_bytes();
+        let [lower_high, lower_low] = window.lower.to_be_bytes();
+        self.set_parameter(PARAM_UPPER_THRESHOLD_H, upper_high)?;
+        self.set_parameter(PARAM_UPPER_THRESHOLD_H + 1, upper_low)?;
+        self.set_parameter(PARAM_LOWER_THRESHOLD_H, lower_high)?;
+        self.set_parameter(PARAM_LOWER_THRESHOLD_H + 1, lower_low)
+    }
+
+    /// Watch the light every ``period_ms`` milliseconds in autonomous mode, and raise the
+    /// interrupt line when it leaves the window.
+    ///
+    /// This needs the ``STANDARD_CHANNELS`` (as set up by ``init``), and adds the
+    /// ``THRESHOLD_CHANNEL`` to them; only that channel is measured autonomously. Forced
+    /// measurements can still be taken in the meantime. Lux windows are converted to counts
+    /// assuming the infrared share of the light seen at the last ``measure_light``.
+    pub fn start_window(&mut self, window: Window, period_ms: u32) -> Result<(), Error>
+    {
+        assert!(self.count >= STANDARD_CHANNELS.len(), "Windows need the standard channels");
+        assert!(window.lower <= window.upper, "Empty window");
+
+        self.pause()?;
+        let config = match window.quantity {
+            Quantity::Lux => THRESHOLD_LUX,
+            Quantity::UvIndex => THRESHOLD_UV,
+        };
+        let armed = ArmedWindow {
+            lower: self.threshold_counts(window.quantity, window.lower),
+            upper: self.threshold_counts(window.quantity, window.upper),
+        };
+
+        self.configure_channel(THRESHOLD_CHANNEL, &config)?;
+        let base = PARAM_ADCCONFIG0 + 4 * THRESHOLD_CHANNEL as u8;
+        self.set_parameter(base + 2, config.adcpost() | ADCPOST_THRESH_WINDOW)?;
+        self.set_thresholds(armed)?;
+        self.window = Some(armed);
+        self.threshold_pending = false;
+        self.enable_channels(THRESHOLD_CHANNEL + 1)?;
+
+        let rate = (period_ms.saturating_mul(1000) / MEASRATE_UNIT_US).clamp(1, 0xffff) as u16;
+        let [high, low] = rate.to_be_bytes();
+        self.set_parameter(PARAM_MEASRATE_H, high)?;
+        self.set_parameter(PARAM_MEASRATE_H + 1, low)?;
+        self.set_parameter(PARAM_MEASCOUNT0, 1)?;
+        for i in 0..THRESHOLD_CHANNEL {
+            self.set_parameter(PARAM_ADCCONFIG0 + 4 * i as u8 + 3, 0)?;
+        }
+        self.set_parameter(base + 3, MEASCONFIG_COUNTER0)?;
+        self.command(CMD_START, 0)
+    }
+
+    /// Check whether the light left the window, typically after the PIC reported a ``uv``
+    /// interrupt.
+    ///
+    /// Each crossing is reported once: After the light rose above the window, the next crossing
+    /// is only reported when it falls below the window's lower bound, and vice versa.
+    pub fn check_crossing(&mut self) -> Result<Option<Crossing>, Error>
+    {
+        let armed = match self.window {
+            Some(armed) => armed,
+            None => return Ok(None),
+        };
+        self.read_status()?;
+        if !core::mem::replace(&mut self.threshold_pending, false) {
+            return Ok(None);
+        }
+
+        let offset: usize = self.channels[..THRESHOLD_CHANNEL].iter().map(ChannelConfig::output_len).sum();
+        let mut buf = [0; 2];
+        self.i2c.write_read(ADDR, &[REG_HOSTOUT0 + offset as u8], &mut buf)?;
+        let value = u16::from_be_bytes(buf);
+
+        let (crossing, rearmed) = if value > armed.upper {
+            (Crossing::Above, ArmedWindow { lower: armed.lower, upper: 0xffff })
+        } else if value < armed.lower {
+            (Crossing::Below, ArmedWindow { lower: 0, upper: armed.upper })
+        } else {
+            return Ok(None);
+        };
+
+        self.pause()?;
+        self.set_thresholds(rearmed)?;
+        self.command(CMD_START, 0)?;
+        Ok(Some(crossing))
+    }
+
+    /// Have the PIC forward the sensor's interrupt: Its INT line is switched to latched mode, and
+    /// the ``uv`` source is enabled along with ``others`` (as the PIC only sets all sources at
+    /// once).
+    pub fn enable_pic_interrupt<D, I>(&self, pic: &mut PIC<D, I>, others: InterruptSet) -> Result<(), Error>
+        where D: DelayUs<u16>,
+              I: Write<Error = i2c_bus::Error> + Read<Error = i2c_bus::Error>,
+    {
+        pic.set_int_mode(&InterruptConfiguration::Latched)?;
+        pic.set_int(InterruptSet { uv: true, ..others })?;
+        Ok(())
+    }
+
+    /// Service the interrupts the PIC reported (eg. from ``PIC::wait_for_interrupt``): If ``uv``
+    /// is pending, check for a crossing, and clear the PIC's ``uv`` flag.
+    pub fn handle_pic_interrupt<D, I>(&mut self, pic: &mut PIC<D, I>, pending: InterruptSet) -> Result<Option<Crossing>, Error>
+        where D: DelayUs<u16>,
+              I: Write<Error = i2c_bus::Error> + Read<Error = i2c_bus::Error>,
+    {
+        if !pending.uv {
+            return Ok(None);
+        }
+        // Reading the sensor's status releases its interrupt line; only then can the PIC's flag
+        // be cleared.
+        let crossing = self.check_crossing()?;
+        pic.clear_int(InterruptSet { uv: true, ..Default::default() })?;
+        Ok(crossing)
+    }
+
+    /// Stop watching the window, returning to the standard channels.
+    pub fn stop_window(&mut self) -> Result<(), Error>
+    {
+        if self.window.take().is_some() {
+            self.pause()?;
+            let base = PARAM_ADCCONFIG0 + 4 * THRESHOLD_CHANNEL as u8;
+            self.set_parameter(base + 2, 0)?;
+            self.set_parameter(base + 3, 0)?;
+            self.threshold_pending = false;
+            self.enable_channels(STANDARD_CHANNELS.len())?;
+        }
+        Ok(())
+    }
+
+    pub fn free(self) -> I2cProxy<'a>
+    {
+        self.i2c
+    }
+}
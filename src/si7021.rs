@@ -0,0 +1,434 @@
+//! Driver for the Si7021 relative humidity and temperature sensor
+//!
+//! The sensor sits at address 0x40 of the board's I2C bus, and is powered with the environmental
+//! sensor group (``PIC::set_env_sensor``), after which it needs up to 80ms to become responsive.
+//!
+//! Measurements can be run blocking (``measure``, ``measure_temperature``), or split into a
+//! ``start_*`` and a non-blocking ``read_*`` call that returns ``WouldBlock`` until the conversion
+//! is done. Both use the sensor's "no hold master" mode: The sensor does not acknowledge reads
+//! while it is converting. (The "hold master" mode would stretch the clock for up to 23ms, keeping
+//! the shared bus claimed and all other users off it for that time.)
+//!
+//! Results are given in centi-degrees Celsius and per-mille relative humidity; the ``humidity``
+//! module derives dew point and similar quantities from them.
+//!
+//! In condensing environments, the sensor saturates and reads 100% until it dries out. Its
+//! built-in heater can speed that up, either controlled manually (``set_heater``) or by an
+//! ``AutoHeater`` that switches it on when the humidity stays high. Readings taken while the heater
+//! is on are skewed (too warm, too dry).
+
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
+
+use crate::i2c_bus::{self, I2cProxy};
+
+pub const ADDR: u8 = 0x40;
+
+const MEASURE_RH_NO_HOLD: u8 = 0xf5;
+const MEASURE_T_NO_HOLD: u8 = 0xf3;
+const READ_T_FROM_RH: u8 = 0xe0;
+const RESET: u8 = 0xfe;
+const WRITE_USER_REG: u8 = 0xe6;
+const READ_USER_REG: u8 = 0xe7;
+const READ_ID_1: [u8; 2] = [0xfa, 0x0f];
+const READ_ID_2: [u8; 2] = [0xfc, 0xc9];
+const READ_FIRMWARE_REVISION: [u8; 2] = [0x84, 0xb8];
+const WRITE_HEATER_REG: u8 = 0x51;
+const READ_HEATER_REG: u8 = 0x11;
+
+/// Resolution bits in the user register (D7 and D0)
+const USER_RES_MASK: u8 = 0x81;
+/// Heater enable bit in the user register
+const USER_HTRE: u8 = 0x04;
+/// Heater current bits in the heater control register
+const HEATER_LEVEL_MASK: u8 = 0x0f;
+
+/// How often a blocking measurement polls for its result (at 1ms intervals) before giving up;
+/// the slowest conversion takes 23ms.
+const POLL_LIMIT: u8 = 50;
+
+#[derive(Debug)]
+pub enum Error {
+    I2c(i2c_bus::Error),
+    /// A checksum did not match
+    Crc,
+    /// A blocking measurement did not complete in time
+    Timeout,
+}
+
+impl From<i2c_bus::Error> for Error {
+    fn from(e: i2c_bus::Error) -> Self
+    {
+        Error::I2c(e)
+    }
+}
+
+/// Measurement resolutions for relative humidity and temperature (in bits)
+///
+/// Lower resolutions convert faster; the full resolution takes about 23ms for a humidity
+/// measurement (which includes a temperature measurement).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    Rh12T14,
+    Rh8T12,
+    Rh10T13,
+    Rh11T11,
+}
+
+impl Resolution {
+    fn to_bits(self) -> u8
+    {
+        match self {
+            Resolution::Rh12T14 => 0x00,
+            Resolution::Rh8T12 => 0x01,
+            Resolution::Rh10T13 => 0x80,
+            Resolution::Rh11T11 => 0x81,
+        }
+    }
+
+    fn from_bits(bits: u8) -> Self
+    {
+        match bits & USER_RES_MASK {
+            0x00 => Resolution::Rh12T14,
+            0x01 => Resolution::Rh8T12,
+            0x80 => Resolution::Rh10T13,
+            _ => Resolution::Rh11T11,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FirmwareRevision {
+    V1_0,
+    V2_0,
+    Unknown(u8),
+}
+
+/// A combined humidity and temperature reading
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    /// Temperature in centi-degrees Celsius
+    pub temperature: i32,
+    /// Relative humidity in per-mille
+    pub humidity: u16,
+}
+
+impl Measurement {
+    /// Dew point in centi-degrees Celsius
+    pub fn dew_point(&self) -> i32
+    {
+        crate::humidity::dew_point(self.temperature, self.humidity)
+    }
+
+    /// Absolute humidity in milligrams per cubic metre
+    pub fn absolute_humidity(&self) -> u32
+    {
+        crate::humidity::absolute_humidity(self.temperature, self.humidity)
+    }
+
+    /// Heat index in centi-degrees Celsius
+    pub fn heat_index(&self) -> i32
+    {
+        crate::humidity::heat_index(self.temperature, self.humidity)
+    }
+}
+
+/// CRC-8 with polynomial x^8 + x^5 + x^4 + 1, as used by the Si7021
+fn crc8(data: &[u8]) -> u8
+{
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Convert a temperature code to centi-degrees Celsius.
+fn temperature_from_code(code: u16) -> i32
+{
+    ((17572 * i32::from(code)) >> 16) - 4685
+}
+
+/// Convert a humidity code to per-mille relative humidity.
+///
+/// The formula produces values slightly out of the 0 to 100% range at the extremes; those are
+/// clamped as recommended by the datasheet.
+fn humidity_from_code(code: u16) -> u16
+{
+    let permille = ((1250 * i32::from(code)) >> 16) - 60;
+    permille.clamp(0, 1000) as u16
+}
+
+pub struct Si7021<'a> {
+    i2c: I2cProxy<'a>,
+}
+
+impl<'a> Si7021<'a> {
+    pub fn new(i2c: I2cProxy<'a>) -> Self
+    {
+        Si7021 { i2c }
+    }
+
+    /// Reset the sensor to its default settings; it takes up to 15ms until it responds again.
+    pub fn reset(&mut self) -> Result<(), Error>
+    {
+        Ok(self.i2c.write(ADDR, &[RESET])?)
+    }
+
+    pub(crate) fn read_user_register(&mut self) -> Result<u8, Error>
+    {
+        let mut buf = [0];
+        self.i2c.write_read(ADDR, &[READ_USER_REG], &mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub(crate) fn write_user_register(&mut self, value: u8) -> Result<(), Error>
+    {
+        Ok(self.i2c.write(ADDR, &[WRITE_USER_REG, value])?)
+    }
+
+    pub fn resolution(&mut self) -> Result<Resolution, Error>
+    {
+        Ok(Resolution::from_bits(self.read_user_register()?))
+    }
+
+    pub fn set_resolution(&mut self, resolution: Resolution) -> Result<(), Error>
+    {
+        // The other bits are reserved or used for the heater, and need to be kept
+        let user = self.read_user_register()?;
+        self.write_user_register((user & !USER_RES_MASK) | resolution.to_bits())
+    }
+
+    pub fn heater(&mut self) -> Result<bool, Error>
+    {
+        Ok(self.read_user_register()? & USER_HTRE != 0)
+    }
+
+    pub fn set_heater(&mut self, on: bool) -> Result<(), Error>
+    {
+        let user = self.read_user_register()?;
+        self.write_user_register(if on { user | USER_HTRE } else { user & !USER_HTRE })
+    }
+
+    /// Heater current setting, from 0 (about 3mA) to 15 (about 94mA) in steps of about 6mA
+    pub fn heater_level(&mut self) -> Result<u8, Error>
+    {
+        let mut buf = [0];
+        self.i2c.write_read(ADDR, &[READ_HEATER_REG], &mut buf)?;
+        Ok(buf[0] & HEATER_LEVEL_MASK)
+    }
+
+    /// Set the heater current (see ``heater_level``); this does not switch the heater on.
+    pub fn set_heater_level(&mut self, level: u8) -> Result<(), Error>
+    {
+        assert!(level <= HEATER_LEVEL_MASK, "Heater level out of range");
+        let mut buf = [0];
+        self.i2c.write_read(ADDR, &[READ_HEATER_REG], &mut buf)?;
+        Ok(self.i2c.write(ADDR, &[WRITE_HEATER_REG, (buf[0] & !HEATER_LEVEL_MASK) | level])?)
+    }
+
+    /// Read the 64-bit electronic serial number.
+    ///
+    /// Its byte SNB_3 (bits 31 to 24) identifies the device type, which is 0x15 for the Si7021.
+    pub fn serial_number(&mut self) -> Result<u64, Error>
+    {
+        // SNA_3, CRC, SNA_2, CRC, SNA_1, CRC, SNA_0, CRC; each CRC covers all bytes before it
+        let mut first = [0; 8];
+        self.i2c.write_read(ADDR, &READ_ID_1, &mut first)?;
+        let sna = [first[0], first[2], first[4], first[6]];
+        for i in 0..4 {
+            if crc8(&sna[..=i]) != first[2 * i + 1] {
+                return Err(Error::Crc);
+            }
+        }
+
+        // SNB_3, SNB_2, CRC, SNB_1, SNB_0, CRC
+        let mut second = [0; 6];
+        self.i2c.write_read(ADDR, &READ_ID_2, &mut second)?;
+        let snb = [second[0], second[1], second[3], second[4]];
+        if crc8(&snb[..2]) != second[2] || crc8(&snb) != second[5] {
+            return Err(Error::Crc);
+        }
+
+        Ok((u64::from(u32::from_be_bytes(sna)) << 32) | u64::from(u32::from_be_bytes(snb)))
+    }
+
+    pub fn firmware_revision(&mut self) -> Result<FirmwareRevision, Error>
+    {
+        let mut buf = [0];
+        self.i2c.write_read(ADDR, &READ_FIRMWARE_REVISION, &mut buf)?;
+        Ok(match buf[0] {
+            0xff => FirmwareRevision::V1_0,
+            0x20 => FirmwareRevision::V2_0,
+            other => FirmwareRevision::Unknown(other),
+        })
+    }
+
+    /// Fetch a measurement result, which the sensor refuses to give (by not acknowledging its
+    /// address) until the conversion is done.
+    fn read_result(&mut self) -> nb::Result<u16, Error>
+    {
+        let mut buf = [0; 3];
+        match self.i2c.read(ADDR, &mut buf) {
+            Err(i2c_bus::Error::Bus(efm32gg_hal::i2c::Error::AddressNack)) => return Err(nb::Error::WouldBlock),
+            Err(e) => return Err(nb::Error::Other(e.into())),
+            Ok(()) => (),
+        }
+        if crc8(&buf[..2]) != buf[2] {
+            return Err(nb::Error::Other(Error::Crc));
+        }
+        Ok(u16::from_be_bytes([buf[0], buf[1]]))
+    }
+
+    /// Start a humidity measurement (which includes a temperature measurement).
+    pub fn start_humidity(&mut self) -> Result<(), Error>
+    {
+        Ok(self.i2c.write(ADDR, &[MEASURE_RH_NO_HOLD])?)
+    }
+
+    /// Fetch the result of a humidity measurement, in per-mille.
+    pub fn read_humidity(&mut self) -> nb::Result<u16, Error>
+    {
+        self.read_result().map(humidity_from_code)
+    }
+
+    /// Start a temperature measurement.
+    pub fn start_temperature(&mut self) -> Result<(), Error>
+    {
+        Ok(self.i2c.write(ADDR, &[MEASURE_T_NO_HOLD])?)
+    }
+
+    /// Fetch the result of a temperature measurement, in centi-degrees Celsius.
+    pub fn read_temperature(&mut self) -> nb::Result<i32, Error>
+    {
+        self.read_result().map(temperature_from_code)
+    }
+
+    /// Read the temperature that was measured along with the last humidity measurement.
+    pub fn temperature_of_last_humidity(&mut self) -> Result<i32, Error>
+    {
+        // No checksum is sent for this one
+        let mut buf = [0; 2];
+        self.i2c.write_read(ADDR, &[READ_T_FROM_RH], &mut buf)?;
+        Ok(temperature_from_code(u16::from_be_bytes(buf)))
+    }
+
+    fn wait<T>(delay: &mut impl DelayMs<u8>, mut read: impl FnMut() -> nb::Result<T, Error>) -> Result<T, Error>
+    {
+        for _ in 0..POLL_LIMIT {
+            match read() {
+                Err(nb::Error::WouldBlock) => delay.delay_ms(1),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Ok(result) => return Ok(result),
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Measure humidity and temperature, waiting for the result.
+    pub fn measure(&mut self, delay: &mut impl DelayMs<u8>) -> Result<Measurement, Error>
+    {
+        self.start_humidity()?;
+        let humidity = Self::wait(delay, || self.read_humidity())?;
+        let temperature = self.temperature_of_last_humidity()?;
+        Ok(Measurement { temperature, humidity })
+    }
+
+    /// Measure only the temperature, waiting for the result.
+    pub fn measure_temperature(&mut self, delay: &mut impl DelayMs<u8>) -> Result<i32, Error>
+    {
+        self.start_temperature()?;
+        Self::wait(delay, || self.read_temperature())
+    }
+
+    pub fn free(self) -> I2cProxy<'a>
+    {
+        self.i2c
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum HeaterState {
+    /// Counting consecutive readings above the threshold
+    Idle(u8),
+    /// Heating for the given number of further readings
+    Heating(u8),
+    /// Heater is off, but the sensor has not cooled down yet
+    Cooling,
+}
+
+/// Automatic heater control for condensing environments
+///
+/// Fed with every humidity reading, this switches the heater on when the humidity has been at or
+/// above the threshold for a number of consecutive readings, and off again after some readings.
+/// ``update`` reports whether the reading was (or the next reading will be) skewed by the heater:
+///
+/// ```ignore
+/// let mut heater = AutoHeater::new(950, 10, 5);
+/// loop {
+///     let m = sensor.measure(&mut delay)?;
+///     if !heater.update(&mut sensor, m.humidity)? {
+///         report(m);
+///     }
+///     delay.delay_ms(1000u16);
+/// }
+/// ```
+pub struct AutoHeater {
+    threshold: u16,
+    on_after: u8,
+    heat_for: u8,
+    state: HeaterState,
+}
+
+impl AutoHeater {
+    /// Create a controller that heats for ``heat_for`` readings once ``on_after`` consecutive
+    /// readings were at or above ``threshold`` (in per-mille).
+    pub fn new(threshold: u16, on_after: u8, heat_for: u8) -> Self
+    {
+        AutoHeater { threshold, on_after: on_after.max(1), heat_for: heat_for.max(1), state: HeaterState::Idle(0) }
+    }
+
+    /// Whether the heater is currently switched on by the controller
+    pub fn is_heating(&self) -> bool
+    {
+        matches!(self.state, HeaterState::Heating(_))
+    }
+
+    /// Process a humidity reading, switching the heater as needed.
+    ///
+    /// Returns true if the reading was taken with the heater on, or shortly after it, and should
+    /// not be used.
+    pub fn update(&mut self, sensor: &mut Si7021, humidity: u16) -> Result<bool, Error>
+    {
+        let (state, skewed) = match self.state {
+            HeaterState::Idle(count) if humidity >= self.threshold => {
+                let count = count + 1;
+                if count >= self.on_after {
+                    sensor.set_heater(true)?;
+                    (HeaterState::Heating(self.heat_for), false)
+                } else {
+                    (HeaterState::Idle(count), false)
+                }
+            }
+            HeaterState::Idle(_) => (HeaterState::Idle(0), false),
+            HeaterState::Heating(1) => {
+                sensor.set_heater(false)?;
+                (HeaterState::Cooling, true)
+            }
+            HeaterState::Heating(left) => (HeaterState::Heating(left - 1), true),
+            HeaterState::Cooling => (HeaterState::Idle(0), true),
+        };
+        self.state = state;
+        Ok(skewed)
+    }
+
+    /// Switch the heater off and start over (eg. before powering the sensor down).
+    pub fn stop(&mut self, sensor: &mut Si7021) -> Result<(), Error>
+    {
+        self.state = HeaterState::Idle(0);
+        sensor.set_heater(false)
+    }
+}
@@ -0,0 +1,156 @@
+//! Low-power waiting in the EFR32's energy modes EM1 to EM3
+//!
+//! A plain WFI or WFE only enters EM1, where the core clock stops but all high frequency
+//! peripherals keep running. ``sleep`` can go deeper:
+//!
+//! * In **EM2**, the high frequency clocks stop. Only the low frequency peripherals keep
+//!   running, so the board can be woken by the buttons and the PIC interrupt line (see the
+//!   ``exti`` module), by the RTCC (eg. through the ``time_driver``) and by the LETIMER0.
+//!
+//! * In **EM3**, the low frequency crystal is stopped as well, so only the GPIO lines can wake
+//!   the board; the RTCC and LETIMER0 are paused for the duration, and the LFXO is restarted on
+//!   wake-up (which can take a while).
+//!
+//! Some things to consider when sleeping deeper than EM1:
+//!
+//! * SysTick does not run in EM2 or EM3. The board's ``SharedDelay`` is not affected (as it never
+//!   sleeps), but it is a busy wait; ``SleepingDelay`` is a drop-in replacement based on the RTCC
+//!   that sleeps while waiting.
+//! * TIMER1 (or any other high frequency timer) stops counting while the chip is in EM2 or EM3,
+//!   and its interrupts can not wake the chip.
+//! * I2C transactions are synchronous (they busy-wait on the peripheral), so they are complete
+//!   before the chip can be put to sleep, and the shared bus needs no special care. (The PIC keeps
+//!   its state on its own.)
+//! * The high frequency clock is restarted from the HFRCO, which is what the board runs on anyway.
+//!
+//! Sleeping is typically done through ``executor::block_on_sleeping``, which runs a future and
+//! sleeps in the given mode whenever it is pending.
+
+use core::future::Future;
+
+use efr32xg1 as registers;
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::executor;
+use crate::time_driver::Timer;
+
+/// Energy mode to enter while waiting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Sleep mode: Only the core is stopped; any interrupt wakes.
+    Em1,
+    /// Deep sleep: Wake-up by GPIO lines, RTCC and LETIMER0.
+    Em2,
+    /// Stop mode: Wake-up by GPIO lines only.
+    Em3,
+}
+
+const SCB_SCR_SLEEPDEEP: u32 = 1 << 2;
+
+pub(crate) fn set_sleepdeep(deep: bool)
+{
+    // unsafe: SCR is only modified in a critical section, and nothing else in the board support
+    // crate uses it.
+    let scb = unsafe { &*cortex_m::peripheral::SCB::ptr() };
+    cortex_m::interrupt::free(|_| unsafe {
+        scb.scr.modify(|scr| if deep { scr | SCB_SCR_SLEEPDEEP } else { scr & !SCB_SCR_SLEEPDEEP });
+    });
+}
+
+fn cmu() -> &'static registers::cmu::RegisterBlock
+{
+    // unsafe: Only the low frequency oscillator enables are touched, which the HAL does not use.
+    unsafe { &*registers::CMU::ptr() }
+}
+
+/// Prepare for entering the mode; returns whether the LFXO needs to be restarted afterwards.
+fn enter(mode: Mode) -> bool
+{
+    // unsafe: EM2 blocking is not used by anything else
+    let emu = unsafe { &*registers::EMU::ptr() };
+    emu.ctrl.modify(|_, w| w.em2block().clear_bit());
+
+    set_sleepdeep(mode != Mode::Em1);
+
+    if mode == Mode::Em3 && cmu().status.read().lfxoens().bit_is_set() {
+        cmu().oscencmd.write(|w| w.lfxodis().set_bit());
+        true
+    } else {
+        false
+    }
+}
+
+/// Undo the preparations of ``enter``.
+fn leave(restart_lfxo: bool)
+{
+    set_sleepdeep(false);
+
+    if restart_lfxo {
+        cmu().oscencmd.write(|w| w.lfxoen().set_bit());
+        while cmu().status.read().lfxordy().bit_is_clear() {}
+    }
+}
+
+/// Sleep in the given mode until an interrupt occurs.
+///
+/// Note that an interrupt that is enabled in the NVIC wakes the chip even if interrupts are
+/// masked (eg. when called in a critical section); the handler then runs after the critical
+/// section ends.
+pub fn sleep(mode: Mode)
+{
+    let restart_lfxo = enter(mode);
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+    leave(restart_lfxo);
+}
+
+/// Sleep in the given mode until an interrupt occurs or an event is signalled (eg. by an
+/// executor's waker); this is what ``executor::block_on_sleeping`` uses.
+pub fn wait_for_event(mode: Mode)
+{
+    let restart_lfxo = enter(mode);
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfe();
+    leave(restart_lfxo);
+}
+
+/// A blocking delay that sleeps in EM2 while waiting
+///
+/// Unlike the SysTick based delays, this keeps working in EM2, as it waits for the RTCC through
+/// the ``time_driver`` (which needs to be initialized, with the RTCC interrupt enabled). Its
+/// resolution is that of the RTCC, about a millisecond.
+#[derive(Clone, Copy, Default)]
+pub struct SleepingDelay;
+
+impl SleepingDelay {
+    pub fn new() -> Self
+    {
+        SleepingDelay
+    }
+
+    fn wait(&mut self, timer: impl Future<Output = ()>)
+    {
+        executor::block_on_sleeping(Mode::Em2, timer)
+    }
+}
+
+impl DelayMs<u32> for SleepingDelay {
+    fn delay_ms(&mut self, ms: u32)
+    {
+        self.wait(Timer::after_ms(ms))
+    }
+}
+
+impl DelayMs<u16> for SleepingDelay {
+    fn delay_ms(&mut self, ms: u16)
+    {
+        self.delay_ms(u32::from(ms))
+    }
+}
+
+impl DelayMs<u8> for SleepingDelay {
+    fn delay_ms(&mut self, ms: u8)
+    {
+        self.delay_ms(u32::from(ms))
+    }
+}
@@ -0,0 +1,34 @@
+//! Time units
+//!
+//! These are modelled after the efm32gg-hal's ``time_util::Hertz``: plain newtypes that make the
+//! unit of a number explicit where the timers of this crate take durations.
+
+/// A duration in microseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Microseconds(pub u32);
+
+/// A duration in milliseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Milliseconds(pub u32);
+
+/// A duration in seconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Seconds(pub u32);
+
+impl From<Milliseconds> for Microseconds {
+    fn from(ms: Milliseconds) -> Self {
+        Microseconds(ms.0.checked_mul(1_000).expect("Duration exceeds u32 microseconds"))
+    }
+}
+
+impl From<Seconds> for Microseconds {
+    fn from(s: Seconds) -> Self {
+        Microseconds(s.0.checked_mul(1_000_000).expect("Duration exceeds u32 microseconds"))
+    }
+}
+
+impl From<Seconds> for Milliseconds {
+    fn from(s: Seconds) -> Self {
+        Milliseconds(s.0.checked_mul(1_000).expect("Duration exceeds u32 milliseconds"))
+    }
+}
@@ -0,0 +1,177 @@
+//! Asynchronous timekeeping on top of the RTCC
+//!
+//! The time driver takes over the board's ``Rtc`` and uses its compare channel 0 to wake up
+//! futures that wait for a point in time. As the RTCC keeps running in EM2, an executor may sleep
+//! deeply while all its tasks are waiting for time to pass.
+//!
+//! To use it, hand it the RTC, enable the RTCC interrupt in the NVIC and forward that interrupt:
+//!
+//! ```ignore
+//! thunderboard_sltb001a::time_driver::init(board.rtc);
+//! board.nvic.enable(efr32xg1::Interrupt::RTCC);
+//!
+//! #[interrupt]
+//! fn RTCC() { thunderboard_sltb001a::time_driver::on_interrupt(); }
+//! ```
+//!
+//! The wall clock and alarm functions of the ``Rtc`` stay available through ``with_rtc``.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use crate::rtc::{Rtc, TICK_HZ};
+use crate::shared::InterruptShared;
+
+/// Number of timers that can wait concurrently; further ones make their tasks poll continuously
+/// until a slot gets free. (Timers that are dropped before they are due keep their slot until
+/// then).
+const SLOTS: usize = 8;
+
+struct Driver {
+    rtc: Rtc,
+    timers: [Option<(u64, Waker)>; SLOTS],
+}
+
+impl Driver {
+    /// Program the compare channel for the earliest waiting timer, and wake any that are due.
+    fn update(&mut self)
+    {
+        let now = self.rtc.uptime_ticks();
+        for slot in self.timers.iter_mut() {
+            if slot.as_ref().map(|(due, _)| *due <= now).unwrap_or(false) {
+                if let Some((_, waker)) = slot.take() {
+                    waker.wake();
+                }
+            }
+        }
+
+        if let Some(next) = self.timers.iter().filter_map(|s| s.as_ref().map(|(due, _)| *due)).min() {
+            self.rtc.set_wakeup(next);
+            // If the counter passed the compare value while it was set, that match was missed
+            if self.rtc.uptime_ticks() >= next {
+                self.update();
+            }
+        }
+    }
+}
+
+static DRIVER: InterruptShared<Driver> = InterruptShared::new();
+
+/// Install the RTC as the time source for the asynchronous timers.
+///
+/// This panics if a time driver was installed already.
+pub fn init(mut rtc: Rtc)
+{
+    rtc.enable_wakeup();
+    if DRIVER.put(Driver { rtc, timers: Default::default() }).is_err() {
+        panic!("Time driver initialized twice");
+    }
+}
+
+/// Handle the RTCC interrupt; to be called from the RTCC interrupt handler.
+pub fn on_interrupt()
+{
+    DRIVER.with(|driver| {
+        driver.rtc.clear_wakeup_flag();
+        driver.update();
+    });
+}
+
+/// Run a closure on the RTC while it is in use by the time driver, eg. to set the wall clock or
+/// to check for alarms from the RTCC interrupt handler.
+///
+/// This returns None if the time driver was not initialized.
+pub fn with_rtc<R>(f: impl FnOnce(&mut Rtc) -> R) -> Option<R>
+{
+    DRIVER.with(|driver| f(&mut driver.rtc))
+}
+
+/// Current time in RTCC ticks (see ``rtc::TICK_HZ``)
+///
+/// This panics if the time driver was not initialized.
+pub fn now() -> u64
+{
+    with_rtc(|rtc| rtc.uptime_ticks()).expect("Time driver not initialized")
+}
+
+/// A future that completes at a given point in time
+pub struct Timer {
+    due: u64,
+}
+
+impl Timer {
+    /// Complete when the RTCC tick counter reaches the given value
+    pub fn at(due: u64) -> Self
+    {
+        Timer { due }
+    }
+
+    /// Complete after the given number of milliseconds (rounded up to whole ticks)
+    pub fn after_ms(ms: u32) -> Self
+    {
+        let ticks = (u64::from(ms) * u64::from(TICK_HZ)).div_ceil(1000);
+        Self::at(now() + ticks)
+    }
+
+    /// Complete after the given number of microseconds (rounded up to whole ticks, so this is not
+    /// suitable for very short delays)
+    pub fn after_us(us: u32) -> Self
+    {
+        let ticks = (u64::from(us) * u64::from(TICK_HZ)).div_ceil(1_000_000);
+        Self::at(now() + ticks)
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()>
+    {
+        let due = self.due;
+        DRIVER.with(|driver| {
+            if driver.rtc.uptime_ticks() >= due {
+                return Poll::Ready(());
+            }
+
+            let waker = cx.waker();
+            // Re-use the slot of an earlier poll, or take a free one
+            let index = driver.timers.iter()
+                .position(|s| s.as_ref().map(|(d, w)| *d == due && w.will_wake(waker)).unwrap_or(false))
+                .or_else(|| driver.timers.iter().position(Option::is_none));
+            match index {
+                Some(i) => {
+                    driver.timers[i] = Some((due, waker.clone()));
+                    driver.update();
+                }
+                // All slots taken: Have the executor poll again right away
+                None => waker.wake_by_ref(),
+            }
+            Poll::Pending
+        }).expect("Time driver not initialized")
+    }
+}
+
+/// Asynchronous counterpart to the blocking delays in the ``delay`` module
+///
+/// The delays are driven by the RTCC and thus have a resolution of about a millisecond, but let
+/// the executor sleep while waiting.
+#[derive(Clone, Copy, Default)]
+pub struct AsyncDelay;
+
+impl AsyncDelay {
+    pub fn new() -> Self
+    {
+        AsyncDelay
+    }
+
+    pub async fn delay_ms(&mut self, ms: u32)
+    {
+        Timer::after_ms(ms).await
+    }
+
+    pub async fn delay_us(&mut self, us: u32)
+    {
+        Timer::after_us(us).await
+    }
+}
@@ -0,0 +1,198 @@
+//! Periodic timers
+//!
+//! Two timers are exposed in a form that implements the embedded-hal ``CountDown`` and
+//! ``Periodic`` traits:
+//!
+//! * ``Timer1`` counts on the high frequency peripheral clock. It has microsecond resolution, but
+//!   can only express periods up to about 3.5 seconds, and does not run in EM2 or deeper.
+//! * ``LETimer0`` counts on the 32.768kHz LFXO. It keeps running in EM2, and can express periods
+//!   from milliseconds to about 18 hours, getting coarser the longer the period is.
+//!
+//! Both can be polled through ``wait()``, or made to raise their interrupt (TIMER1 or LETIMER0,
+//! which still needs to be enabled in the NVIC) whenever a period elapsed. When the interrupt is
+//! used, the handler needs to acknowledge it through the respective ``interrupt_unpend``, and
+//! ``wait()`` should not be used at the same time as the two would compete for the same flag.
+
+use efr32xg1 as registers;
+use efm32gg_hal::timer::InterruptFlag;
+use embedded_hal::timer::{CountDown, Periodic};
+use void::Void;
+
+use crate::lfclock;
+use crate::time::{Microseconds, Milliseconds};
+
+/// Frequency of HFPERCLK, which feeds TIMER1.
+///
+/// FIXME: Like the HAL's HFCoreClk, this assumes that nothing has been changed about the clocks
+/// since reset.
+const HFPERCLK_HZ: u32 = 19_000_000;
+
+/// Find the smallest prescaler exponent (up to ``max_presc``) that makes a period of ``ticks``
+/// fit into a 16 bit counter, and the resulting top value.
+fn fit_16bit(ticks: u64, max_presc: u8) -> Option<(u8, u16)>
+{
+    let ticks = ticks.max(1);
+    (0..=max_presc)
+        .find(|p| (ticks >> p) <= 0x10000)
+        .map(|p| (p, ((ticks >> p).max(1) - 1) as u16))
+}
+
+/// TIMER1 as a periodic count-down timer
+pub struct Timer1 {
+    timer: efm32gg_hal::timer::Timer1,
+}
+
+impl Timer1 {
+    pub fn new(timer: efm32gg_hal::timer::Timer1) -> Self
+    {
+        Timer1 { timer }
+    }
+
+    /// Raise the TIMER1 interrupt every time a period elapses.
+    pub fn listen(&mut self)
+    {
+        self.timer.interrupt_enable(InterruptFlag::OF);
+    }
+
+    /// Stop raising the TIMER1 interrupt when a period elapses.
+    pub fn unlisten(&mut self)
+    {
+        // unsafe: Only touches the interrupt enable register, no routing
+        unsafe { self.timer.with_registers(|r| r.ien.modify(|_, w| w.of().clear_bit())) };
+    }
+
+    /// Acknowledge an elapsed period; to be called from the TIMER1 interrupt handler.
+    pub fn interrupt_unpend()
+    {
+        efm32gg_hal::timer::Timer1::interrupt_unpend(InterruptFlag::OF);
+    }
+
+    /// Stop the timer and give back the underlying HAL timer.
+    pub fn free(mut self) -> efm32gg_hal::timer::Timer1
+    {
+        // unsafe: Only stopping, no routing
+        unsafe { self.timer.with_registers(|r| r.cmd.write(|w| w.stop().set_bit())) };
+        self.timer
+    }
+}
+
+impl CountDown for Timer1 {
+    type Time = Microseconds;
+
+    /// Start counting down periods of the given length.
+    ///
+    /// This panics if the period exceeds what TIMER1 can express at its largest prescaler
+    /// (65536 * 1024 / 19MHz, about 3.5 seconds); use the ``LETimer0`` for longer periods.
+    fn start<T>(&mut self, count: T)
+        where T: Into<Microseconds>
+    {
+        let ticks = u64::from(count.into().0) * u64::from(HFPERCLK_HZ) / 1_000_000;
+        let (presc, top) = fit_16bit(ticks, 10).expect("Period too long for TIMER1");
+
+        // unsafe: This only configures the counter, no routing
+        unsafe { self.timer.with_registers(|r| {
+            r.cmd.write(|w| w.stop().set_bit());
+            r.ctrl.modify(|_, w| w.mode().up().clksel().preschfperclk().presc().bits(presc));
+            r.top.write(|w| w.top().bits(top));
+            r.cnt.write(|w| w.cnt().bits(0));
+            r.ifc.write(|w| w.of().set_bit());
+            r.cmd.write(|w| w.start().set_bit());
+        }) };
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void>
+    {
+        if efm32gg_hal::timer::Timer1::interrupt_is_pending(InterruptFlag::OF) {
+            Self::interrupt_unpend();
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl Periodic for Timer1 {}
+
+/// LETIMER0 as a periodic count-down timer that keeps running in EM2
+///
+/// The LFXO is only started when the timer is first started, which can take a noticeable time
+/// after power-up.
+pub struct LETimer0 {
+    register: registers::LETIMER0,
+}
+
+impl LETimer0 {
+    pub fn new(register: registers::LETIMER0) -> Self
+    {
+        LETimer0 { register }
+    }
+
+    /// Raise the LETIMER0 interrupt every time a period elapses.
+    pub fn listen(&mut self)
+    {
+        self.register.ien.modify(|_, w| w.uf().set_bit());
+    }
+
+    /// Stop raising the LETIMER0 interrupt when a period elapses.
+    pub fn unlisten(&mut self)
+    {
+        self.register.ien.modify(|_, w| w.uf().clear_bit());
+    }
+
+    /// Acknowledge an elapsed period; to be called from the LETIMER0 interrupt handler.
+    pub fn interrupt_unpend()
+    {
+        // unsafe: Write-only access to a clear register, which only affects the flag given
+        unsafe { &*registers::LETIMER0::ptr() }.ifc.write(|w| w.uf().set_bit());
+    }
+
+    fn command(&mut self, cmd: impl FnOnce(&mut registers::letimer0::cmd::W) -> &mut registers::letimer0::cmd::W)
+    {
+        while self.register.syncbusy.read().cmd().bit_is_set() {}
+        self.register.cmd.write(cmd);
+    }
+
+    /// Stop the timer and give back the underlying register block.
+    pub fn free(mut self) -> registers::LETIMER0
+    {
+        self.command(|w| w.stop().set_bit());
+        self.register
+    }
+}
+
+impl CountDown for LETimer0 {
+    type Time = Milliseconds;
+
+    /// Start counting down periods of the given length.
+    ///
+    /// The resolution of the period is 1/32768s for periods up to 2 seconds, and halves with every
+    /// doubling of the period. This panics for periods beyond what can be expressed at the largest
+    /// prescaler (65536 seconds, about 18 hours).
+    fn start<T>(&mut self, count: T)
+        where T: Into<Milliseconds>
+    {
+        let ticks = u64::from(count.into().0) * u64::from(lfclock::LFXO_HZ) / 1_000;
+        let (presc, top) = fit_16bit(ticks, 15).expect("Period too long for LETIMER0");
+
+        self.command(|w| w.stop().set_bit());
+        lfclock::enable_letimer0(presc);
+
+        self.register.ctrl.write(|w| w.repmode().free().comp0top().set_bit());
+        self.register.comp0.write(|w| unsafe { w.comp0().bits(top) });
+        self.register.cnt.write(|w| unsafe { w.cnt().bits(top) });
+        self.register.ifc.write(|w| w.uf().set_bit());
+        self.command(|w| w.start().set_bit());
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void>
+    {
+        if self.register.if_.read().uf().bit_is_set() {
+            Self::interrupt_unpend();
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl Periodic for LETimer0 {}
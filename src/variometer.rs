@@ -0,0 +1,227 @@
+//! Vertical speed from high-rate pressure readings
+//!
+//! The ``Variometer`` runs the BMP280 in normal mode, and turns the pressure readings into a
+//! stream of timestamped altitude and climb rate samples. ``MAX_RATE`` runs the sensor at its
+//! highest output data rate (about 145 samples per second), leaving the noise to the sensor's IIR
+//! filter and the variometer's own filter; ``LOW_NOISE`` oversamples the pressure at half that
+//! rate:
+//!
+//! ```ignore
+//! let mut vario = Variometer::start(bmp, Altimeter::default(), variometer::MAX_RATE).await?;
+//! loop {
+//!     let sample = vario.next().await?;
+//!     log(sample.timestamp, sample.altitude_cm, sample.climb_cm_s);
+//! }
+//! ```
+//!
+//! Altitude and climb rate are estimated by a complementary filter: The altitude is predicted
+//! from the previous estimate and the climb rate (which is trustworthy in the short term), and
+//! corrected towards the barometric altitude (which is trustworthy in the long term, but noisy).
+//! The climb rate in turn follows the corrections. Where an accelerometer reading of the vertical
+//! acceleration is available, feeding it in through ``set_vertical_acceleration`` improves the
+//! prediction.
+//!
+//! Samples are paced by the ``time_driver``, which needs to be initialized; timestamps are in its
+//! RTCC ticks.
+
+use crate::barometer::Altimeter;
+use crate::bmp280::{Bmp280, Config, Error, Filter, Measurement, Oversampling, Standby};
+use crate::rtc::TICK_HZ;
+use crate::time_driver::{self, Timer};
+
+/// Sensor configuration for the highest output data rate
+pub const MAX_RATE: Config = Config {
+    temperature: Oversampling::X1,
+    pressure: Oversampling::X1,
+    filter: Filter::X16,
+    standby: Standby::Ms0_5,
+};
+
+/// Sensor configuration with less noise per sample, at about half the data rate
+pub const LOW_NOISE: Config = Config {
+    temperature: Oversampling::X1,
+    pressure: Oversampling::X4,
+    filter: Filter::X4,
+    standby: Standby::Ms0_5,
+};
+
+/// How often a read that returned the previous measurement again is retried (a tick later each)
+const DUPLICATE_RETRIES: u8 = 3;
+
+/// Filter gains
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gains {
+    /// Share of the difference between predicted and measured altitude that goes into the
+    /// altitude estimate, in Q16
+    pub altitude_q16: u32,
+    /// Share of that difference that goes into the climb rate estimate (per sample period), in
+    /// Q16
+    pub climb_q16: u32,
+}
+
+impl Default for Gains {
+    /// Gains that settle within about a second, while keeping the climb rate noise in the order of
+    /// 10cm/s
+    fn default() -> Self
+    {
+        Gains { altitude_q16: 9830, climb_q16: 655 }
+    }
+}
+
+/// A filtered altitude and climb rate estimate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// RTCC ticks (see ``rtc::TICK_HZ``) at which the measurement was read
+    pub timestamp: u64,
+    /// Altitude relative to the altimeter's reference, in centimetres
+    pub altitude_cm: i32,
+    /// Vertical speed in centimetres per second, positive when climbing
+    pub climb_cm_s: i32,
+    /// The measurement the estimate is based on
+    pub measurement: Measurement,
+}
+
+fn standby_us(standby: Standby) -> u64
+{
+    match standby {
+        Standby::Ms0_5 => 500,
+        Standby::Ms62_5 => 62_500,
+        Standby::Ms125 => 125_000,
+        Standby::Ms250 => 250_000,
+        Standby::Ms500 => 500_000,
+        Standby::Ms1000 => 1_000_000,
+        Standby::Ms2000 => 2_000_000,
+        Standby::Ms4000 => 4_000_000,
+    }
+}
+
+pub struct Variometer<'a> {
+    bmp: Bmp280<'a>,
+    altimeter: Altimeter,
+    gains: Gains,
+    /// Sampling period in ticks
+    period: u64,
+    /// Tick at which the next sample is due
+    next: u64,
+    last_timestamp: u64,
+    last_measurement: Measurement,
+    /// Estimates in 1/256 cm and 1/256 cm/s
+    altitude: i64,
+    climb: i64,
+    /// Vertical acceleration in cm/s^2
+    acceleration: i32,
+}
+
+impl<'a> Variometer<'a> {
+    /// Configure the (initialized) sensor for streaming and start measuring; the first
+    /// measurement initializes the altitude estimate, with the climb rate at zero.
+    ///
+    /// The configuration is typically ``MAX_RATE`` or ``LOW_NOISE``. Samples are taken once per
+    /// measurement cycle, rounded to whole ticks; a read that comes too early is retried.
+    pub async fn start(mut bmp: Bmp280<'a>, altimeter: Altimeter, config: Config) -> Result<Variometer<'a>, Error>
+    {
+        bmp.set_config(config)?;
+        bmp.start_normal()?;
+
+        let cycle_us = u64::from(config.measurement_time_us()) + standby_us(config.standby);
+        let period = ((cycle_us * u64::from(TICK_HZ) + 500_000) / 1_000_000).max(1);
+
+        Timer::at(time_driver::now() + period).await;
+        let measurement = bmp.read()?;
+        let timestamp = time_driver::now();
+
+        Ok(Variometer {
+            bmp,
+            altimeter,
+            gains: Gains::default(),
+            period,
+            next: timestamp + period,
+            last_timestamp: timestamp,
+            last_measurement: measurement,
+            altitude: i64::from(altimeter.altitude_cm(&measurement)) << 8,
+            climb: 0,
+            acceleration: 0,
+        })
+    }
+
+    pub fn set_gains(&mut self, gains: Gains)
+    {
+        self.gains = gains;
+    }
+
+    /// Change the altitude reference, eg. after calibrating it; the estimate jumps accordingly.
+    pub fn set_altimeter(&mut self, altimeter: Altimeter)
+    {
+        let shift = altimeter.altitude_cm(&self.last_measurement) - self.altimeter.altitude_cm(&self.last_measurement);
+        self.altitude += i64::from(shift) << 8;
+        self.altimeter = altimeter;
+    }
+
+    /// Feed in the current vertical acceleration (in cm/s^2, positive upwards, without gravity),
+    /// which is used in the predictions until it is updated again.
+    pub fn set_vertical_acceleration(&mut self, acceleration_cm_s2: i32)
+    {
+        self.acceleration = acceleration_cm_s2;
+    }
+
+    /// Wait for the next measurement and return the updated estimate.
+    ///
+    /// Samples are taken at a fixed rate; if the caller falls behind, samples are skipped rather
+    /// than delivered in a burst.
+    pub async fn next(&mut self) -> Result<Sample, Error>
+    {
+        Timer::at(self.next).await;
+
+        let mut measurement = self.bmp.read()?;
+        for _ in 0..DUPLICATE_RETRIES {
+            if measurement != self.last_measurement {
+                break;
+            }
+            // Read before the sensor completed a new measurement
+            Timer::at(time_driver::now() + 1).await;
+            measurement = self.bmp.read()?;
+        }
+        let timestamp = time_driver::now();
+
+        self.next += self.period;
+        if self.next <= timestamp {
+            self.next = timestamp + self.period;
+        }
+
+        self.update(timestamp, &measurement);
+        self.last_measurement = measurement;
+
+        Ok(Sample {
+            timestamp,
+            altitude_cm: (self.altitude >> 8) as i32,
+            climb_cm_s: (self.climb >> 8) as i32,
+            measurement,
+        })
+    }
+
+    /// Advance the filter to the given time with a new measurement.
+    fn update(&mut self, timestamp: u64, measurement: &Measurement)
+    {
+        let ticks = i64::from(TICK_HZ);
+        let dt = (timestamp - self.last_timestamp).max(1) as i64;
+        self.last_timestamp = timestamp;
+
+        // Prediction from the climb rate (and acceleration), over dt ticks
+        let acceleration = i64::from(self.acceleration) << 8;
+        self.climb += acceleration * dt / ticks;
+        let predicted = self.altitude + self.climb * dt / ticks;
+
+        // Correction towards the measurement
+        let measured = i64::from(self.altimeter.altitude_cm(measurement)) << 8;
+        let residual = measured - predicted;
+        self.altitude = predicted + ((residual * i64::from(self.gains.altitude_q16)) >> 16);
+        self.climb += ((residual * i64::from(self.gains.climb_q16)) >> 16) * ticks / dt;
+    }
+
+    /// Stop streaming, putting the sensor to sleep.
+    pub fn stop(mut self) -> Result<Bmp280<'a>, Error>
+    {
+        self.bmp.sleep()?;
+        Ok(self.bmp)
+    }
+}